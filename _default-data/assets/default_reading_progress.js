@@ -0,0 +1,44 @@
+// Reading progress bar for pages built with reading_progress: true
+(function () {
+    var content = document.querySelector("[data-reading-progress]");
+    if (!content) {
+        return;
+    }
+
+    var bar = document.createElement("div");
+    bar.className = "reading-progress-bar";
+    bar.setAttribute("role", "progressbar");
+    bar.setAttribute("aria-label", "Reading progress");
+    bar.setAttribute("aria-valuemin", "0");
+    bar.setAttribute("aria-valuemax", "100");
+    document.body.prepend(bar);
+
+    var headings = content.querySelectorAll("h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]");
+
+    function update() {
+        var rect = content.getBoundingClientRect();
+        var scrollable = rect.height - window.innerHeight;
+        var progress = scrollable > 0 ? Math.min(Math.max(-rect.top / scrollable, 0), 1) : 1;
+        var percent = Math.round(progress * 100);
+        bar.style.width = percent + "%";
+        bar.setAttribute("aria-valuenow", String(percent));
+        content.setAttribute("data-reading-progress", String(percent));
+
+        // The last heading scrolled past is the current section
+        var current = null;
+        headings.forEach(function (heading) {
+            if (heading.getBoundingClientRect().top <= 1) {
+                current = heading.id;
+            }
+        });
+        if (current) {
+            content.setAttribute("data-current-section", current);
+        } else {
+            content.removeAttribute("data-current-section");
+        }
+    }
+
+    window.addEventListener("scroll", update, { passive: true });
+    window.addEventListener("resize", update);
+    update();
+})();
@@ -1,16 +1,16 @@
 use std::{
-    cell::RefCell, collections::HashMap, error::Error, fs::{self, File}, io::{Read, Write}, path::{Path, PathBuf}, process::Command, rc::Rc, time::{Duration, SystemTime}
+    cell::{OnceCell, RefCell}, collections::{BTreeMap, BTreeSet, HashMap}, error::Error, fs::{self, File}, io::{BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, path::{Component, Path, PathBuf}, process::Command, rc::Rc, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, SystemTime}
 };
 
-use chrono::{DateTime, Local, Utc};
+use chrono::{format::{Item, StrftimeItems}, DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, Utc};
 use clap::Parser;
 use notify::{RecommendedWatcher, Watcher};
-use pulldown_cmark::{html, Event, Options, Tag};
+use pulldown_cmark::{html, Event, HeadingLevel, Options, Tag, TagEnd};
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
 
 
-/**
+/*
  * Features:
  * - make static websites
  * - HTML templates, markdown to HTML, copy assets to output
@@ -21,7 +21,7 @@ use serde_yaml::Value;
  */
 
 
-/**
+/*
  * TODO / Bug Fixes:
  * - more fun things (interactive or social)
  * - different styles and style pallet
@@ -36,7 +36,7 @@ use serde_yaml::Value;
 
 // ========== Data Structures ==========
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct Config {
     pub config_path: Option<String>,
     pub input_dir: String,
@@ -46,12 +46,107 @@ pub struct Config {
     pub variants: Option<Vec<String>>,
     pub generate_robots_txt: Option<bool>,
     pub generate_sitemap_xml: Option<bool>,
+    pub template_dirs: Option<Vec<String>>,
+    pub preview: Option<bool>,
+    pub preview_output_dir: Option<String>,
+    pub strict: Option<bool>,
+    pub strict_variables: Option<bool>,
+    pub canonical_host: Option<String>,
+    pub heading_shift: Option<usize>,
+    pub sort_by: Option<String>,
+    pub pretty_xml: Option<bool>,
+    pub default_robots: Option<String>,
+    pub clean_urls: Option<bool>,
+    pub trailing_slash: Option<bool>,
+    pub head_snippet: Option<String>,
+    pub head_snippet_file: Option<String>,
+    pub body_snippet: Option<String>,
+    pub body_snippet_file: Option<String>,
+    pub snippet_variants: Option<Vec<String>>,
+    pub posts_dir: Option<String>,
+    pub check_charset: Option<bool>,
+    pub utf8_bom: Option<bool>,
+    #[serde(skip)]
+    pub since: Option<DateTime<Utc>>,
+    pub default_changefreq: Option<String>,
+    pub default_priority: Option<f32>,
+    pub generate_humans_txt: Option<bool>,
+    pub humans_txt: Option<String>,
+    pub generate_security_txt: Option<bool>,
+    pub security_txt: Option<SecurityTxtConfig>,
+    pub data_table_hide_empty: Option<bool>,
+    pub type_layouts: Option<HashMap<String, String>>,
+    pub build_revision: Option<String>,
+    pub build_revision_env: Option<String>,
+    pub jobs: Option<usize>,
+    pub profile: Option<Profile>,
+    pub site_url: Option<String>,
+    pub content_dir: Option<String>,
+    pub timezone: Option<String>,
+    pub homepage: Option<String>,
+    pub unsupported_site_data: Option<String>,
+    pub heading_anchors: Option<bool>,
+    pub check_links: Option<bool>,
+    pub markdown_extensions: Option<Vec<String>>,
+    pub production_variants: Option<Vec<String>>,
+    pub env_banner_message: Option<String>,
+    pub env_banner_class: Option<String>,
+    pub env_banner_html: Option<String>,
+    pub tag_url: Option<String>,
+    pub category_url: Option<String>,
+    pub generate_opensearch: Option<bool>,
+    pub opensearch: Option<OpenSearchConfig>,
+    pub changes_json: Option<bool>,
+    pub allow_raw_html: Option<bool>,
+    pub reading_progress: Option<bool>,
+    pub lookup_key: Option<String>,
+    pub variant_languages: Option<HashMap<String, VariantLanguage>>,
+    pub index_filename: Option<String>,
+    pub share_networks: Option<Vec<ShareNetwork>>,
+    pub offline_bundle: Option<bool>,
+    pub incremental: Option<bool>,
+    pub generate_rss: Option<bool>,
+    pub excerpt_words: Option<usize>,
+    pub sitemap_path: Option<String>,
+    pub robots_path: Option<String>,
+    pub sitemap_lastmod_from_git: Option<bool>,
+    pub minify_html: Option<bool>,
+    pub fingerprint_assets: Option<bool>,
+    pub compile_scss: Option<bool>,
+    pub pretty_urls: Option<bool>,
+    pub paginate: Option<usize>,
+    #[serde(skip)]
+    pub force: Option<bool>,
+    #[serde(skip)]
+    pub drafts: Option<bool>,
+    // Files a watch rebuild was set off by; pages using none of them are kept from the last build
+    #[serde(skip)]
+    pub changed_paths: Option<Vec<PathBuf>>,
+    // Problems with the keys of the config file it was loaded from, reported by validate
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
 }
 
-type FrontMatter = HashMap<String, String>;
+// Presets that fill in settings the config and CLI leave unset
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, schemars::JsonSchema, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    // Local development: localhost site.url, readable XML
+    Dev,
+    // Production: site.url from data/site.yaml, compact XML
+    Prod,
+}
+
+type FrontMatter = HashMap<String, FrontMatterValue>;
+
+// Page number and total pages of one page of a paginated listing
+type PageNumber = (usize, usize);
+type Redirects = Vec<(String, String)>;
 type TemplateContextPtr = Rc<RefCell<TemplateContext>>;
 type TemplateFunc = dyn Fn(&[String], Option<&str>, TemplateContextPtr, &mut GlobalContext) -> String + 'static;
 type TemplateFuncPtr = Rc<TemplateFunc>;
+type PostRenderTransform = dyn Fn(String, TemplateContextPtr, &GlobalContext) -> String + 'static;
+type PostRenderTransformPtr = Rc<PostRenderTransform>;
 
 #[derive(Debug)]
 struct TemplateContext {
@@ -79,6 +174,7 @@ enum TemplateNode {
     IfBlock {
         condition: String,
         true_branch: Rc<TemplateNode>,
+        elif_branches: Vec<(String, Rc<TemplateNode>)>,
         false_branch: Option<Rc<TemplateNode>>,
     },
     ForEachBlock {
@@ -91,11 +187,18 @@ enum TemplateNode {
         args: Vec<String>,
         block_content: Option<String>,
     },
+    // A tag that isn't a function; expands a macro of that name if one is defined by render time,
+    // otherwise renders the tag unchanged for variable substitution
+    MacroCall {
+        name: String,
+        args: Vec<String>,
+        tag: String,
+    },
     StringContent(String),
     Composite(Vec<TemplateNode>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct RobotsConfig {
     // Global crawl delay in seconds
     pub crawl_delay: Option<u32>,
@@ -116,7 +219,26 @@ pub struct RobotsConfig {
     pub auto_include_generated_html: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl RobotsConfig {
+    // Used when generate_robots_txt is on but there's no data/robots_config.yaml
+    pub fn allow_all(sitemap_url: String) -> Self {
+        RobotsConfig {
+            crawl_delay: None,
+            sitemap: Some(sitemap_url),
+            user_agents: Some(vec![RobotsUserAgentRules {
+                user_agents: vec!["*".to_string()],
+                allow: Some(vec!["/".to_string()]),
+                disallow: None,
+                crawl_delay: None,
+            }]),
+            global_rules: None,
+            auto_disallow_non_included_html: None,
+            auto_include_generated_html: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct RobotsUserAgentRules {
     // Multiple user agents these rules apply to
     pub user_agents: Vec<String>,
@@ -131,7 +253,7 @@ pub struct RobotsUserAgentRules {
     pub crawl_delay: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, schemars::JsonSchema)]
 pub struct RobotsGlobalRules {
     // Paths to allow (relative to site root)
     pub allow: Option<Vec<String>>,
@@ -140,6 +262,141 @@ pub struct RobotsGlobalRules {
     pub disallow: Option<Vec<String>>,
 }
 
+// Fields for /.well-known/security.txt (RFC 9116)
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SecurityTxtConfig {
+    // Where to report vulnerabilities (mailto:, https: or tel: URIs), at least one required
+    pub contact: Vec<String>,
+
+    // When this file should be considered stale, as an RFC 3339 timestamp (required)
+    pub expires: String,
+
+    // Links to encryption keys for contacting the security team
+    pub encryption: Option<Vec<String>>,
+
+    // Link to a page thanking security researchers
+    pub acknowledgments: Option<String>,
+
+    // Comma-separated language codes, e.g. "en, fr"
+    pub preferred_languages: Option<String>,
+
+    // Link to the vulnerability disclosure policy
+    pub policy: Option<String>,
+
+    // Link to security-related job openings
+    pub hiring: Option<String>,
+
+    // Where this security.txt is published
+    pub canonical: Option<String>,
+}
+
+// A service linked from share_buttons
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ShareNetwork {
+    // Link text, e.g. "Mastodon"
+    pub name: String,
+
+    // Share link with {url} and {title} placeholders, filled in URL-encoded. Optional for
+    // twitter, x, mastodon, linkedin and email, which have built-in links
+    pub url: Option<String>,
+}
+
+// Language and text direction of a variant, for the root <html> tag
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct VariantLanguage {
+    // Language tag, e.g. "fr" or "pt-BR" (default "en")
+    pub lang: Option<String>,
+
+    // Text direction, "ltr" or "rtl" (default "ltr")
+    pub dir: Option<String>,
+}
+
+// Fields for the OpenSearch description document that lets browsers search the site
+#[derive(Clone, Debug, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct OpenSearchConfig {
+    // Name shown in the browser's search engine list, 16 characters or fewer (required)
+    pub short_name: String,
+
+    // Search results URL with {searchTerms} where the query goes, e.g. "/search?q={searchTerms}" (required)
+    pub search_url: String,
+
+    // Longer description of the search, defaults to short_name
+    pub description: Option<String>,
+
+    // Icon for the search engine, e.g. "/favicon.ico"
+    pub icon: Option<String>,
+}
+
+// Content hashes of the pages written by the last build, keyed by path relative to the output root
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BuildManifest {
+    pages: BTreeMap<String, String>,
+}
+
+// What an incremental build knows about the previous build, to skip pages whose inputs are unchanged
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BuildCache {
+    // Hash of the config, site strings and every page's front matter, which listings, links and
+    // layouts can depend on; when it changes every page is rebuilt
+    site_hash: String,
+    pages: BTreeMap<String, CachedPage>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct CachedPage {
+    // Modification times (milliseconds since the epoch, 0 when missing) of the page's source and
+    // each layout, partial and data file it used
+    files: BTreeMap<String, u64>,
+    // Content hashes of the files written for the page, by path relative to the output root
+    outputs: BTreeMap<String, String>,
+}
+
+// A front matter value: plain YAML values are scalars, lists keep their items and nested
+// mappings their keys. Data holds a file loaded through json_data, for foreach to iterate
+#[derive(Clone, Debug, PartialEq)]
+enum FrontMatterValue {
+    Scalar(String),
+    List(Vec<String>),
+    Map(HashMap<String, FrontMatterValue>),
+    Data(Value),
+}
+
+impl FrontMatterValue {
+    // The value of a scalar; lists and mappings have none
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Scalar(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // The items of a list, or of a comma-separated scalar such as tags: "a, b"
+    fn items(&self) -> Vec<&str> {
+        match self {
+            Self::Scalar(value) => front_matter_list(value),
+            Self::List(items) => items.iter().map(|item| item.trim()).filter(|item| !item.is_empty()).collect(),
+            Self::Map(_) | Self::Data(_) => vec![],
+        }
+    }
+}
+
+impl From<String> for FrontMatterValue {
+    fn from(value: String) -> Self {
+        Self::Scalar(value)
+    }
+}
+
+// Lists are shown comma-separated, so {{ tags }} renders "a, b"
+impl std::fmt::Display for FrontMatterValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Scalar(value) => f.write_str(value),
+            Self::List(items) => f.write_str(&items.join(", ")),
+            Self::Map(_) | Self::Data(_) => Ok(()),
+        }
+    }
+}
+
 // Represents a single entry in a sitemap.xml file
 #[derive(Debug, Clone)]
 pub struct SitemapXmlNode {
@@ -182,11 +439,68 @@ pub struct AlternateLink {
     pub lang: String,
 }
 
+// A {{ macro name(params) }} ... {{ endmacro }} definition
+#[derive(Debug)]
+struct TemplateMacro {
+    params: Vec<String>,
+    body: Rc<TemplateNode>,
+}
+
+// How deeply macros may expand inside each other before expansion is cut off
+const MAX_MACRO_DEPTH: usize = 16;
+
+// Front matter and location of a page, collected before rendering so
+// listing functions can see the whole site
+#[derive(Debug, Clone)]
+struct PageInfo {
+    source_path: String,
+    output_path: PathBuf,
+    front_matter: FrontMatter,
+}
+
+// A heading of a page's markdown as rendered, for {{ toc }}
+#[derive(Debug, Clone)]
+struct PageHeading {
+    level: usize,
+    id: Option<String>,
+    text: String,
+}
+
 struct GlobalContext {
     cfg: Config,
     layout_cache: HashMap<String, Rc<TemplateNode>>,
+    // Parsed include partials and their files by path, and the partials currently being rendered
+    partial_cache: HashMap<String, (Rc<TemplateNode>, PathBuf)>,
+    include_stack: Vec<String>,
+    // Problems template functions ran into while rendering the current page, which fail the build
+    render_errors: Vec<String>,
+    // Files read while building the current page, for incremental builds, and the files each
+    // cached layout was loaded from (including its parents and data)
+    page_dependencies: RefCell<BTreeSet<PathBuf>>,
+    layout_files: HashMap<String, BTreeSet<PathBuf>>,
     site_strings: HashMap<String, String>,
     functions: HashMap<String, TemplateFuncPtr>,
+    post_render_transforms: Vec<(String, PostRenderTransformPtr)>,
+    pages: Vec<PageInfo>,
+    homepage: Option<String>,
+    head_snippet: Option<String>,
+    body_snippet: Option<String>,
+    macros: HashMap<String, Rc<TemplateMacro>>,
+    macro_stack: Vec<String>,
+    // Markdown link targets and headings by page source path, and heading IDs by page output path
+    page_links: HashMap<String, Vec<String>>,
+    page_headings: HashMap<String, Vec<PageHeading>>,
+    page_anchors: HashMap<PathBuf, Vec<String>>,
+    // Site string overrides from each content directory's _site.yaml, empty when it has none, and
+    // the ones applying to the page being built, which win over site_strings
+    directory_strings: HashMap<PathBuf, HashMap<String, String>>,
+    page_site_strings: HashMap<String, String>,
+    // Last commit date of each file under the content directory, read from git once per build
+    git_commit_dates: OnceCell<HashMap<PathBuf, DateTime<Utc>>>,
+    // Fingerprinted name of each asset by its path under assets/, empty unless fingerprint_assets is on
+    asset_manifest: BTreeMap<String, String>,
+    // Files each compiled stylesheet in asset_manifest was made from: its source and its imports
+    asset_sources: BTreeMap<String, Vec<PathBuf>>,
 }
 
 // ========== Struct Implementations ====
@@ -201,14 +515,42 @@ impl TemplateContext {
         }))
     }
     
+    // Lists and json_data files are also added as data, so blocks like foreach can iterate them
     pub fn add_front_matter(&mut self, front_matter: &FrontMatter) {
-        self.strings.extend(front_matter.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.strings.extend(front_matter_strings(front_matter));
+        for (key, value) in front_matter {
+            match value {
+                FrontMatterValue::List(items) => {
+                    let items = items.iter().map(|item| Value::String(item.clone())).collect();
+                    self.json_data.insert(key.clone(), Value::Sequence(items));
+                }
+                FrontMatterValue::Data(data) => {
+                    self.json_data.insert(key.clone(), data.clone());
+                }
+                _ => {}
+            }
+        }
     }
     
     pub fn get_string(&self, key: &str) -> Option<String> {
         self.strings.get(key).cloned()
             .or_else(|| self.parent.as_ref()?.borrow().get_string(key))
     }
+
+    pub fn get_json_data(&self, key: &str) -> Option<Value> {
+        self.json_data.get(key).cloned()
+            .or_else(|| self.parent.as_ref()?.borrow().get_json_data(key))
+    }
+
+    // A front matter value as it was written: a list keeps its items, anything else is a scalar
+    pub fn get_front_matter_value(&self, key: &str) -> Option<FrontMatterValue> {
+        match self.get_json_data(key) {
+            Some(Value::Sequence(items)) => Some(FrontMatterValue::List(
+                items.iter().filter_map(|item| item.as_str().map(str::to_string)).collect(),
+            )),
+            _ => self.get_string(key).map(FrontMatterValue::Scalar),
+        }
+    }
 }
 
 impl TemplateNode {
@@ -243,15 +585,21 @@ impl TemplateNode {
     }
 
     fn apply_all_substitutions(&self, s: String, context: TemplateContextPtr, global_context: &mut GlobalContext, front_matter: &FrontMatter) -> String {
-        context.borrow_mut().add_front_matter(front_matter);
-        let output = Self::perform_substitutions_strings(s, front_matter);
+        let output = Self::perform_substitutions_strings(s, &front_matter_strings(front_matter));
         Self::apply_substitutions(&output, context, global_context)
     }
     
     pub fn render(&self, context: TemplateContextPtr, global_context: &mut GlobalContext) -> String {
         match self {
-            Self::Page { content_node, parent, front_matter, .. } => {
+            Self::Page { path, content_node, parent, front_matter, output_path } => {
                 let page_context = TemplateContext::new(Some(context.clone()));
+                page_context.borrow_mut().strings.insert("page.url".to_string(), global_context.page_url(output_path));
+                page_context.borrow_mut().strings.insert("page.source_path".to_string(), path.clone());
+                page_context.borrow_mut().strings.insert("page.output_path".to_string(), global_context.site_relative_path(output_path));
+                if global_context.homepage.as_ref() == Some(path) {
+                    page_context.borrow_mut().strings.insert("page.is_homepage".to_string(), "true".to_string());
+                }
+                page_context.borrow_mut().add_front_matter(front_matter);
 
                 let output = self.apply_all_substitutions(
                     content_node.render(page_context.clone(), global_context),
@@ -267,6 +615,7 @@ impl TemplateNode {
                 })
             }
             Self::Layout { content_node, parent, front_matter, .. } => {
+                context.borrow_mut().add_front_matter(front_matter);
                 let output = self.apply_all_substitutions(
                     content_node.render(context.clone(), global_context),
                     context.clone(),
@@ -280,10 +629,12 @@ impl TemplateNode {
                     parent.render(layout_context, global_context)
                 })
             }
-            Self::IfBlock { condition, true_branch, false_branch } => {
-                let ctx = context.borrow();
-                if ctx.get_string(condition).is_some() {
+            Self::IfBlock { condition, true_branch, elif_branches, false_branch } => {
+                if Self::evaluate_condition(condition, &context, global_context) {
                     true_branch.render(context.clone(), global_context)
+                } else if let Some((_, elif_branch)) = elif_branches.iter()
+                    .find(|(condition, _)| Self::evaluate_condition(condition, &context, global_context)) {
+                    elif_branch.render(context.clone(), global_context)
                 } else if let Some(false_branch) = false_branch {
                     false_branch.render(context.clone(), global_context)
                 } else {
@@ -291,19 +642,31 @@ impl TemplateNode {
                 }
             }
             Self::ForEachBlock { key, item_name, body } => {
-                let ctx = context.borrow();
-                if let Some(Value::Sequence(items)) = ctx.json_data.get(key) {
+                let items = context.borrow().get_json_data(key);
+                if let Some(Value::Sequence(items)) = items {
                     items.iter()
                     .map(|item| {
+                        // A mapping's fields are available as {{ field }} and {{ item.field }},
+                        // a plain value as {{ item }}
                         let new_ctx = TemplateContext::new(Some(context.clone()));
-                        if let Value::Mapping(map) = item {
-                            for (k, v) in map {
-                                if let (Some(k), Some(v)) = (k.as_str(), v.as_str()) {
-                                    new_ctx.borrow_mut().strings.insert(k.to_string(), v.to_string());
+                        match item {
+                            Value::Mapping(map) => {
+                                for (k, v) in map {
+                                    if let (Some(k), Some(v)) = (k.as_str(), front_matter_scalar(v)) {
+                                        new_ctx.borrow_mut().strings.insert(format!("{}.{}", item_name, k), v.clone());
+                                        new_ctx.borrow_mut().strings.insert(k.to_string(), v);
+                                    }
+                                }
+                            }
+                            other => {
+                                if let Some(v) = front_matter_scalar(other) {
+                                    new_ctx.borrow_mut().strings.insert(item_name.clone(), v);
                                 }
                             }
                         }
-                        body.render(new_ctx, global_context)
+                        let output = body.render(new_ctx.clone(), global_context);
+                        let item_strings = new_ctx.borrow().strings.clone();
+                        Self::perform_substitutions_strings(output, &item_strings)
                     })
                     .collect()
                 } else {
@@ -317,6 +680,30 @@ impl TemplateNode {
                     name.clone()
                 }
             }
+            Self::MacroCall { name, args, tag } => {
+                let Some(template_macro) = global_context.macros.get(name).cloned() else {
+                    return tag.clone();
+                };
+                if global_context.macro_stack.len() >= MAX_MACRO_DEPTH {
+                    eprintln!(
+                        "Warning: macro {} not expanded, macros nest more than {} deep ({})",
+                        name, MAX_MACRO_DEPTH, global_context.macro_stack.join(" -> ")
+                    );
+                    return String::new();
+                }
+
+                // Arguments are bound to the macro's parameters by position; missing ones are empty
+                let bindings = template_macro.params.iter().enumerate()
+                    .map(|(i, param)| (param.clone(), args.get(i).cloned().unwrap_or_default()))
+                    .collect::<HashMap<_, _>>();
+                let macro_context = TemplateContext::new(Some(context.clone()));
+                macro_context.borrow_mut().strings.extend(bindings.clone());
+
+                global_context.macro_stack.push(name.clone());
+                let output = template_macro.body.render(macro_context, global_context);
+                global_context.macro_stack.pop();
+                Self::perform_substitutions_strings(output, &bindings)
+            }
             Self::StringContent(s) => s.clone(),
             Self::Composite(template_nodes) => {
                 template_nodes.iter()
@@ -327,6 +714,34 @@ impl TemplateNode {
         }
     }
     
+    // Conditions are either a key that must be present in the context or site data,
+    // "variant_is <name>" to branch on the variant being built, "env <NAME>" for an environment flag,
+    // or "date_between <start> <end>" for the build date
+    fn evaluate_condition(condition: &str, context: &TemplateContextPtr, global_context: &GlobalContext) -> bool {
+        match condition.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["variant_is", name] => global_context.cfg.variant.as_deref() == Some(*name),
+            ["env", name] => env_flag_is_set(name),
+            ["date_between", start, end] => build_date_between(start, end, global_context.cfg.timezone.as_deref()),
+            ["date_between", ..] => {
+                eprintln!("Warning: if_date_between needs a start and end date, e.g. 2024-12-01 2024-12-31");
+                false
+            }
+            _ => {
+                let value_of = |key: &str| context.borrow().get_string(key.trim())
+                    .or_else(|| global_context.site_string(key.trim()));
+                if let Some((key, literal)) = condition.split_once("!=") {
+                    value_of(key).as_deref() != Some(condition_literal(literal).as_str())
+                } else if let Some((key, literal)) = condition.split_once("==") {
+                    value_of(key).as_deref() == Some(condition_literal(literal).as_str())
+                } else if let Some(key) = condition.strip_prefix('!') {
+                    !value_of(key).is_some_and(|value| is_truthy(&value))
+                } else {
+                    value_of(condition).is_some_and(|value| is_truthy(&value))
+                }
+            }
+        }
+    }
+    
     fn perform_substitutions_str(s: String, k: &str, v: &str) -> String {
         let mut s = s;
         for k in &[format!(" {} ", k), k.to_string()] {
@@ -355,7 +770,8 @@ impl TemplateNode {
         if let Some(parent) = ctx.parent.clone() {
             Self::apply_substitutions(&output, parent, global_context)
         } else {
-            global_context.site_strings.iter()
+            global_context.page_site_strings.iter()
+                .chain(&global_context.site_strings)
                 .fold(output, |acc, (key, value)| Self::perform_substitutions_str(acc, key, value))
         }
     }
@@ -375,10 +791,14 @@ impl TemplateNode {
                 println!("{:indent$}📦 {} (Layout)", "", name, indent = indent);
                 content_node.print_tree(indent + 1);
             }
-            Self::IfBlock { condition, true_branch, false_branch } => {
+            Self::IfBlock { condition, true_branch, elif_branches, false_branch } => {
                 println!("{:indent$}❓ if {} (Conditional)", "", condition, indent = indent);
                 println!("{:indent$}├── Then:", "", indent = indent + 2);
                 true_branch.print_tree(indent + 4);
+                for (condition, elif_branch) in elif_branches {
+                    println!("{:indent$}├── Else if {}:", "", condition, indent = indent + 2);
+                    elif_branch.print_tree(indent + 4);
+                }
                 if let Some(false_branch) = false_branch {
                     println!("{:indent$}└── Else:", "", indent = indent + 2);
                     false_branch.print_tree(indent + 4);
@@ -395,6 +815,9 @@ impl TemplateNode {
                     println!("{:indent$}└── Block: {}...", "", content.replace("\n", "").chars().take(30).collect::<String>(), indent = indent + 2);
                 }
             }
+            Self::MacroCall { tag, .. } => {
+                println!("{:indent$}📝 {}", "", tag, indent = indent);
+            }
             Self::StringContent(s) => {
                 println!("{:indent$}📝 {}...", "", s.replace("\n", "").chars().take(50).collect::<String>(), indent = indent);
             }
@@ -415,6 +838,24 @@ impl TemplateNode {
             _ => None,
         }
     }
+
+    // Arguments of the first call to a function in this node, its branches and its layouts
+    fn function_args(&self, function: &str) -> Option<&[String]> {
+        match self {
+            Self::Page { content_node, parent, .. } | Self::Layout { content_node, parent, .. } => {
+                content_node.function_args(function).or_else(|| parent.as_ref()?.function_args(function))
+            }
+            Self::IfBlock { true_branch, elif_branches, false_branch, .. } => {
+                true_branch.function_args(function)
+                    .or_else(|| elif_branches.iter().find_map(|(_, branch)| branch.function_args(function)))
+                    .or_else(|| false_branch.as_ref()?.function_args(function))
+            }
+            Self::ForEachBlock { body, .. } => body.function_args(function),
+            Self::Func { name, args, .. } if name == function => Some(args),
+            Self::Composite(nodes) => nodes.iter().find_map(|node| node.function_args(function)),
+            _ => None,
+        }
+    }
 }
 
 impl GlobalContext {
@@ -422,21 +863,49 @@ impl GlobalContext {
         Self {
             cfg,
             layout_cache: HashMap::new(),
+            partial_cache: HashMap::new(),
+            include_stack: Vec::new(),
+            render_errors: Vec::new(),
+            page_dependencies: RefCell::new(BTreeSet::new()),
+            layout_files: HashMap::new(),
             site_strings: HashMap::new(),
             functions: HashMap::new(),
+            post_render_transforms: Vec::new(),
+            pages: Vec::new(),
+            homepage: None,
+            head_snippet: None,
+            body_snippet: None,
+            macros: HashMap::new(),
+            macro_stack: Vec::new(),
+            page_links: HashMap::new(),
+            page_headings: HashMap::new(),
+            page_anchors: HashMap::new(),
+            directory_strings: HashMap::new(),
+            page_site_strings: HashMap::new(),
+            git_commit_dates: OnceCell::new(),
+            asset_manifest: BTreeMap::new(),
+            asset_sources: BTreeMap::new(),
         }
     }
 
-    pub fn new_with_defaults(cfg: Config) -> Self {
+    pub fn new_with_defaults(cfg: Config) -> Result<Self, Box<dyn Error>> {
         let mut x = Self::new(cfg);
         x.with_default_strings();
         x.with_default_funcs();
-        x.load_site_data();
-        x
+        x.with_default_post_render_transforms();
+        x.load_site_data()?;
+        Ok(x)
     }
 
     pub fn with_default_strings(&mut self) -> &mut Self {
-        self.site_strings.insert("build_revision".to_string(), Self::get_git_revision());
+        self.site_strings.insert("build_revision".to_string(), self.get_build_revision());
+        if let Some(variant) = &self.cfg.variant {
+            self.site_strings.insert("variant".to_string(), variant.clone());
+        }
+        // Lets layouts load the reading progress script only when there's a bar to draw
+        if self.cfg.reading_progress.unwrap_or(false) {
+            self.site_strings.insert("reading_progress".to_string(), "true".to_string());
+        }
         self
     }
 
@@ -451,9 +920,30 @@ impl GlobalContext {
             &|args, _, _, _| args.first().map_or(String::new(), |s| s.to_lowercase()),
         );
 
+        // The current date, optionally with a strftime format, e.g. {{ date "%B %e, %Y" }}
         self.register_function(
             "date",
-            &|_, _, _, _| Local::now().format("%Y-%m-%d").to_string(),
+            &|args, _, _, _| {
+                let format = date_format_arg(args.first(), "date", "%Y-%m-%d");
+                Local::now().format(format).to_string()
+            },
+        );
+
+        // A date value of the page or site formatted with an optional strftime format, e.g.
+        // {{ date-from "date" "%B %Y" }}. Values that aren't dates are shown as they are
+        self.register_function(
+            "date-from",
+            &|args, _, ctx, global| {
+                let Some(key) = args.first() else {
+                    eprintln!("Warning: date-from needs a key, e.g. {{{{ date-from \"date\" \"%B %Y\" }}}}");
+                    return String::new();
+                };
+                let Some(value) = ctx.borrow().get_string(key).or_else(|| global.site_string(key)) else {
+                    return String::new();
+                };
+                let format = date_format_arg(args.get(1), "date-from", "%Y-%m-%d");
+                format_date_value(&value, format).unwrap_or(value)
+            },
         );
 
         self.register_function(
@@ -466,11 +956,23 @@ impl GlobalContext {
             &|_, _, _, _| Local::now().format("%c").to_string(),
         );
 
+        self.register_function(
+            "modified-datetime",
+            &|_, _, ctx, global| page_modified_time(&ctx, global, "%Y-%m-%d %H:%M:%S"),
+        );
+
         self.register_function(
             "modified-datetime-pretty",
-            &|_, _, ctx, _| {
-                ctx.borrow().path // get mod time from file path
-            }.format("%c").to_string(),
+            &|_, _, ctx, global| page_modified_time(&ctx, global, "%c"),
+        );
+
+        // Source file modification time of the current page, optionally with a strftime format
+        self.register_function(
+            "page_modified",
+            &|args, _, ctx, global| {
+                let format = date_format_arg(args.first(), "page_modified", "%Y-%m-%d %H:%M:%S");
+                page_modified_time(&ctx, global, format)
+            },
         );
 
         self.register_function(
@@ -487,32 +989,536 @@ impl GlobalContext {
         self.register_function(
             "tags_html",
             &|_, _, ctx, _| {
-                if let Some(tags_str) = ctx.borrow().get_string("tags") {
-                    format!("<p><b>Tags:</b> {}</p>", tags_str)
+                if let Some(tags) = ctx.borrow().get_front_matter_value("tags") {
+                    let items: Vec<String> = tags.items().into_iter().map(escape_xml).collect();
+                    format!("<p><b>Tags:</b> {}</p>", items.join(", "))
                 } else {
                     "".to_string()
                 }
             },
         );
 
+        self.register_function("all_tags", &|args, _, _, global| {
+            let url_pattern = global.cfg.tag_url.clone().unwrap_or_else(|| "/tags/{slug}/".to_string());
+            global.render_taxonomy_links("tags", &url_pattern, args.first().map(String::as_str))
+        });
+
+        self.register_function("all_categories", &|args, _, _, global| {
+            let url_pattern = global.cfg.category_url.clone().unwrap_or_else(|| "/categories/{slug}/".to_string());
+            global.render_taxonomy_links("categories", &url_pattern, args.first().map(String::as_str))
+        });
+
         self.register_function(
             "categories_html",
             &|_, _, ctx, _| {
-                if let Some(categories_str) = ctx.borrow().get_string("categories") {
-                    format!("<p><b>Categories:</b> {}</p>", categories_str)
+                if let Some(categories) = ctx.borrow().get_front_matter_value("categories") {
+                    let items: Vec<String> = categories.items().into_iter().map(escape_xml).collect();
+                    format!("<p><b>Categories:</b> {}</p>", items.join(", "))
                 } else {
                     "".to_string()
                 }
             },
         );
 
+        self.register_function(
+            "get",
+            &|args, _, ctx, global| {
+                let Some(key) = args.first() else {
+                    return String::new();
+                };
+                ctx.borrow().get_string(key)
+                    .or_else(|| global.site_string(key))
+                    .or_else(|| args.get(1).cloned())
+                    .unwrap_or_default()
+            },
+        );
+
+        // Like get, but an empty value also falls back, e.g. {{ default author "Anonymous" }}
+        self.register_function(
+            "default",
+            &|args, _, ctx, global| {
+                let Some(key) = args.first() else {
+                    eprintln!("Warning: default needs a key and a fallback, e.g. {{{{ default author \"Anonymous\" }}}}");
+                    return String::new();
+                };
+                ctx.borrow().get_string(key)
+                    .or_else(|| global.site_string(key))
+                    .filter(|value| !value.trim().is_empty())
+                    .or_else(|| args.get(1).cloned())
+                    .unwrap_or_default()
+            },
+        );
+
         self.register_function(
             "relative-url",
             &|args, _, _, ctx| {
-                ctx.relative_url(args.get(0).unwrap())
+                ctx.relative_url(args.first().unwrap())
             },
         );
 
+        // An HTML value cut to about N visible characters, with every open tag closed
+        self.register_function("truncate_html", &|args, _, ctx, global| {
+            let Some(key) = args.first() else {
+                eprintln!("Warning: truncate_html needs a key, e.g. {{{{ truncate_html content 200 }}}}");
+                return String::new();
+            };
+            let max_chars = match args.get(1).map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    eprintln!("Warning: truncate_html length {} is not a number, using 200", args[1]);
+                    200
+                }
+                None => 200,
+            };
+            let html = ctx.borrow().get_string(key)
+                .or_else(|| global.site_string(key))
+                .unwrap_or_default();
+            truncate_html(&html, max_chars)
+        });
+
+        // Binds the fields of the data file entry matching a page value, e.g. {{ lookup "data/authors.yaml" author }}
+        // finds the entry whose id (or slug) is the page's author and makes {{ author.bio }} available
+        self.register_function("lookup", &|args, _, ctx, global| {
+            let (Some(path), Some(key)) = (args.first(), args.get(1)) else {
+                eprintln!("Warning: lookup needs a data file and a key, e.g. {{{{ lookup \"data/authors.yaml\" author }}}}");
+                return String::new();
+            };
+            let Some(value) = ctx.borrow().get_string(key) else {
+                eprintln!("Warning: lookup found no {} value to look up in {}", key, path);
+                return String::new();
+            };
+            let full_path = global.cfg.relative_to_config_path(&PathBuf::from(path));
+            let data = match global.load_yaml_data_merge_env_variant(full_path.to_str().unwrap()) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("Warning: lookup could not load {}: {}", path, e);
+                    return String::new();
+                }
+            };
+
+            match find_data_entry(&data, value.trim(), global.cfg.lookup_key.as_deref()) {
+                Some(entry) => {
+                    let mut fields = HashMap::new();
+                    match global.load_site_data_entries(key, entry, &mut fields) {
+                        Ok(()) => ctx.borrow_mut().strings.extend(fields),
+                        Err(e) => eprintln!("Warning: lookup could not use the {} entry in {}: {}", value, path, e),
+                    }
+                }
+                None => eprintln!("Warning: lookup found no entry for {} {} in {}", key, value, path),
+            }
+            String::new()
+        });
+
+        // The page's excerpt front matter as HTML, else what comes before its <!--more--> marker,
+        // else its first paragraph cut to excerpt_words words
+        self.register_function("excerpt", &|_, _, ctx, _| {
+            let ctx = ctx.borrow();
+            match ctx.get_string("excerpt") {
+                Some(excerpt) => render_markdown(&excerpt),
+                None => ctx.get_string("page.excerpt").unwrap_or_default(),
+            }
+        });
+
+        // A URL-safe slug of a key's value or of literal text, e.g. {{ slugify "Héllo, World!" }} -> hello-world
+        self.register_function("slugify", &|args, _, ctx, global| {
+            match args.first() {
+                Some(arg) => slugify(&string_arg(arg, &ctx, global)),
+                None => {
+                    eprintln!("Warning: slugify needs a key or text, e.g. {{{{ slugify title }}}}");
+                    String::new()
+                }
+            }
+        });
+
+        // A key's value or literal text cut to a number of characters at a word boundary, or to a
+        // number of words, with an ellipsis when anything was cut: {{ truncate description 200 }}
+        self.register_function("truncate", &|args, _, ctx, global| {
+            truncate_function("truncate", args, &ctx, global, truncate_chars)
+        });
+
+        self.register_function("truncatewords", &|args, _, ctx, global| {
+            truncate_function("truncatewords", args, &ctx, global, truncate_words)
+        });
+
+        // A markdown value rendered to HTML, e.g. {{ markdown summary }}; markdown-inline drops the
+        // paragraph around a single line for use inside other elements
+        self.register_function("markdown", &|args, _, ctx, global| {
+            let Some(key) = args.first() else {
+                eprintln!("Warning: markdown needs a key, e.g. {{{{ markdown summary }}}}");
+                return String::new();
+            };
+            ctx.borrow().get_string(key)
+                .or_else(|| global.site_string(key))
+                .map(|value| render_markdown(&value))
+                .unwrap_or_default()
+        });
+
+        self.register_function("markdown-inline", &|args, _, ctx, global| {
+            let Some(key) = args.first() else {
+                eprintln!("Warning: markdown-inline needs a key, e.g. {{{{ markdown-inline summary }}}}");
+                return String::new();
+            };
+            ctx.borrow().get_string(key)
+                .or_else(|| global.site_string(key))
+                .map(|value| {
+                    let html = render_markdown(&value);
+                    let html = html.trim_end();
+                    match html.strip_prefix("<p>").and_then(|inner| inner.strip_suffix("</p>")) {
+                        Some(inner) if !inner.contains("<p>") => inner.to_string(),
+                        _ => html.to_string(),
+                    }
+                })
+                .unwrap_or_default()
+        });
+
+        // The text of an HTML value, for plain-text outputs
+        self.register_function("strip_html", &|args, _, ctx, global| {
+            let Some(key) = args.first() else {
+                eprintln!("Warning: strip_html needs a key, e.g. {{{{ strip_html content }}}}");
+                return String::new();
+            };
+            let html = ctx.borrow().get_string(key)
+                .or_else(|| global.site_string(key))
+                .unwrap_or_default();
+            strip_html(&html)
+        });
+
+        self.register_function("canonical_link", &|_, _, ctx, global| {
+            let ctx = ctx.borrow();
+            let url = ctx.get_string("canonical")
+                .map(|canonical| global.relative_url(&canonical))
+                .or_else(|| ctx.get_string("page.url"))
+                .unwrap_or_default();
+            format!("<link rel=\"canonical\" href=\"{}\">\n<meta property=\"og:url\" content=\"{}\">", url, url)
+        });
+
+        // The current page's absolute URL, for sharing links
+        self.register_function("permalink", &|_, _, ctx, global| {
+            let Some(output_path) = ctx.borrow().get_string("page.output_path") else {
+                return String::new();
+            };
+            global.permalink(&output_path).unwrap_or_else(|| {
+                eprintln!("Warning: permalink needs site.url to be set in data/site.yaml");
+                String::new()
+            })
+        });
+
+        // Lets browsers discover the site's OpenSearch description
+        self.register_function("opensearch_link", &|_, _, _, global| {
+            match &global.cfg.opensearch {
+                Some(opensearch) if global.cfg.generate_opensearch.unwrap_or(false) => format!(
+                    "<link rel=\"search\" type=\"application/opensearchdescription+xml\" title=\"{}\" href=\"{}\">",
+                    escape_xml(&opensearch.short_name),
+                    global.relative_url(OPENSEARCH_PATH)
+                ),
+                _ => String::new(),
+            }
+        });
+
+        // lang and dir attributes for the root <html> tag, from the variant's variant_languages entry
+        self.register_function("html_lang", &|_, _, _, global| {
+            global.variant_language()
+                .and_then(|language| language.lang.clone())
+                .unwrap_or_else(|| "en".to_string())
+        });
+
+        self.register_function("html_dir", &|_, _, _, global| {
+            global.variant_language()
+                .and_then(|language| language.dir.clone())
+                .unwrap_or_else(|| "ltr".to_string())
+        });
+
+        // An inline SVG QR code of the current page's absolute URL, optionally sized in pixels
+        self.register_function("qr_code", &|args, _, ctx, global| {
+            let size = match args.first().map(|size| size.parse::<u32>()) {
+                Some(Ok(size)) => size,
+                Some(Err(_)) => {
+                    eprintln!("Warning: qr_code size {} is not a number, using 128", args[0]);
+                    128
+                }
+                None => 128,
+            };
+            ctx.borrow().get_string("page.output_path")
+                .and_then(|output_path| global.permalink(&output_path))
+                .and_then(|url| qr_code_modules(url.as_bytes()))
+                .map(|modules| qr_code_svg(&modules, size))
+                .unwrap_or_default()
+        });
+
+        // Links sharing the current page's permalink and title on each configured share network
+        self.register_function("share_buttons", &|_, _, ctx, global| {
+            let Some(networks) = global.cfg.share_networks.as_ref().filter(|networks| !networks.is_empty()) else {
+                return String::new();
+            };
+            let ctx = ctx.borrow();
+            let Some(url) = ctx.get_string("page.output_path").and_then(|output_path| global.permalink(&output_path)) else {
+                return String::new();
+            };
+            let title = ctx.get_string("title").unwrap_or_default();
+
+            let mut html = String::from("<nav class=\"share-buttons\">\n");
+            for network in networks {
+                let Some(template) = network.url.as_deref().or_else(|| default_share_url(&network.name)) else {
+                    eprintln!("Warning: share network {} has no url", network.name);
+                    continue;
+                };
+                let href = template
+                    .replace("{url}", &url_encode(&url))
+                    .replace("{title}", &url_encode(&title));
+                html.push_str(&format!(
+                    "<a class=\"share-{}\" href=\"{}\" target=\"_blank\" rel=\"noopener\">{}</a>\n",
+                    slugify(&network.name), escape_xml(&href), escape_xml(&network.name)
+                ));
+            }
+            html.push_str("</nav>");
+            html
+        });
+
+        // Renders a file from the template directories in the current context, e.g. a nav bar shared by layouts
+        self.register_function("include", &|args, _, ctx, global| {
+            let Some(path) = args.first() else {
+                eprintln!("Warning: include needs a file, e.g. {{{{ include \"partials/nav.html\" }}}}");
+                return String::new();
+            };
+            if global.include_stack.contains(path) {
+                eprintln!("Warning: {} includes itself ({} -> {}), skipping it", path, global.include_stack.join(" -> "), path);
+                return String::new();
+            }
+            match global.get_partial(path) {
+                Ok(partial) => {
+                    global.include_stack.push(path.clone());
+                    let output = partial.render(ctx, global);
+                    global.include_stack.pop();
+                    output
+                }
+                Err(e) => {
+                    let including = global.include_stack.last().cloned()
+                        .or_else(|| ctx.borrow().get_string("page.source_path"))
+                        .unwrap_or_default();
+                    global.render_errors.push(format!("{} includes {}, but {}", including, path, e));
+                    String::new()
+                }
+            }
+        });
+
+        self.register_function("robots_meta", &|_, _, ctx, global| {
+            ctx.borrow().get_string("robots")
+                .or_else(|| global.cfg.default_robots.clone())
+                .filter(|robots| !robots.trim().is_empty())
+                .map(|robots| format!("<meta name=\"robots\" content=\"{}\">", robots.trim()))
+                .unwrap_or_default()
+        });
+
+        // BreadcrumbList structured data for the current page; root pages have no trail to show
+        self.register_function("breadcrumb_jsonld", &|_, _, ctx, global| {
+            let Some(source_path) = ctx.borrow().get_string("page.source_path") else {
+                return String::new();
+            };
+            let trail = global.breadcrumb_trail(&source_path);
+            if trail.len() < 2 {
+                return String::new();
+            }
+            let items = trail.iter().enumerate()
+                .map(|(i, (name, url))| serde_json::json!({
+                    "@type": "ListItem",
+                    "position": i + 1,
+                    "name": name,
+                    "item": url,
+                }))
+                .collect::<Vec<_>>();
+            let breadcrumbs = serde_json::json!({
+                "@context": "https://schema.org",
+                "@type": "BreadcrumbList",
+                "itemListElement": items,
+            });
+            // A title containing </script> would otherwise end the script element early
+            format!(
+                "<script type=\"application/ld+json\">\n{}\n</script>",
+                breadcrumbs.to_string().replace("</", "<\\/")
+            )
+        });
+
+        // Inlines an SVG file, adding name=value arguments to its root <svg> element
+        self.register_function("svg", &|args, _, _, global| {
+            let Some(path) = args.first() else {
+                eprintln!("Warning: svg requires a path argument");
+                return String::new();
+            };
+            let full_path = global.cfg.relative_to_config_path(&PathBuf::from(path));
+            global.record_dependency(&full_path);
+            let content = match fs::read_to_string(&full_path) {
+                Ok(content) => content,
+                Err(e) => {
+                    eprintln!("Warning: svg could not read {}: {}", full_path.display(), e);
+                    return String::new();
+                }
+            };
+            let attributes = args[1..].iter()
+                .filter_map(|arg| arg.split_once('='))
+                .map(|(name, value)| (name.trim().to_string(), value.trim().trim_matches('"').to_string()))
+                .collect::<Vec<_>>();
+            inject_svg_attributes(&content, &attributes).unwrap_or_else(|| {
+                eprintln!("Warning: {} has no <svg> element", full_path.display());
+                String::new()
+            })
+        });
+
+        // Links to the current page's headings, ## and ### by default, e.g. {{ toc }} or {{ toc 4 }}
+        // to include #### headings too
+        self.register_function("toc", &|args, _, ctx, global| {
+            let Some(source_path) = ctx.borrow().get_string("page.source_path") else {
+                return String::new();
+            };
+            let max_level = match args.first().map(|n| n.parse::<usize>()) {
+                Some(Ok(n)) => n.clamp(2, 6),
+                Some(Err(_)) => {
+                    eprintln!("Warning: toc depth {} is not a number, using 3", args[0]);
+                    3
+                }
+                None => 3,
+            };
+            global.page_headings.get(&source_path)
+                .map(|headings| render_toc(headings, 2, max_level))
+                .unwrap_or_default()
+        });
+
+        // URL of a file under assets/, through the fingerprinted name when fingerprint_assets is on,
+        // e.g. {{ asset "style.css" }}
+        self.register_function("asset", &|args, _, _, global| {
+            let Some(path) = args.first() else {
+                eprintln!("Warning: asset needs a path, e.g. {{{{ asset \"style.css\" }}}}");
+                return String::new();
+            };
+            let path = path.trim_start_matches('/');
+            global.asset_url(path.strip_prefix("assets/").unwrap_or(path))
+        });
+
+        self.register_function("rel_path", &|args, _, ctx, global| {
+            let Some(target) = args.first() else {
+                return String::new();
+            };
+            let from = ctx.borrow().get_string("page.output_path").unwrap_or_default();
+            global.relative_link(&from, target)
+        });
+
+        // Stylesheets listed in the page's styles front matter
+        self.register_function("page_head", &|_, _, ctx, global| {
+            ctx.borrow().get_front_matter_value("styles")
+                .map(|styles| styles.items().into_iter()
+                    .map(|href| format!("<link rel=\"stylesheet\" href=\"{}\">", escape_xml(&global.linked_file_url(href))))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+                .unwrap_or_default()
+        });
+
+        // Scripts listed in the page's scripts front matter
+        self.register_function("page_scripts", &|_, _, ctx, global| {
+            ctx.borrow().get_front_matter_value("scripts")
+                .map(|scripts| scripts.items().into_iter()
+                    .map(|src| format!("<script src=\"{}\"></script>", escape_xml(&global.linked_file_url(src))))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+                .unwrap_or_default()
+        });
+
+        // A file (or a 1-based inclusive line range of it) as a fenced code block
+        self.register_function("code", &|args, _, _, global| {
+            let Some(path) = args.first() else {
+                eprintln!("Warning: code requires a path argument");
+                return String::new();
+            };
+            let (language, range) = match args.get(1) {
+                Some(arg) if arg.parse::<usize>().is_err() => (arg.as_str(), &args[2..]),
+                _ => ("", &args[1..]),
+            };
+            let full_path = global.cfg.relative_to_config_path(&PathBuf::from(path));
+            global.record_dependency(&full_path);
+            let content = match fs::read_to_string(&full_path) {
+                Ok(content) => content,
+                Err(e) => {
+                    eprintln!("Warning: code could not read {}: {}", full_path.display(), e);
+                    return String::new();
+                }
+            };
+
+            let lines = content.lines().collect::<Vec<_>>();
+            let start = range.first().and_then(|s| s.parse::<usize>().ok()).unwrap_or(1);
+            let end = range.get(1).and_then(|s| s.parse::<usize>().ok()).unwrap_or(lines.len());
+            if start == 0 || start > end || end > lines.len() {
+                eprintln!("Warning: code lines {}-{} are out of range for {} ({} lines)", start, end, path, lines.len());
+                return String::new();
+            }
+            render_code_block(&lines[start - 1..end].join("\n"), language)
+        });
+
+        // Previous/next and numbered links between the pages of a paginated listing, showing an
+        // optional number of pages either side of the current one (default 2)
+        self.register_function("pagination", &|args, _, ctx, global| {
+            let ctx = ctx.borrow();
+            let number = |key: &str| ctx.get_string(key).and_then(|n| n.trim().parse::<usize>().ok());
+            let (Some(current), Some(total)) = (number("page_number"), number("total_pages")) else {
+                return String::new();
+            };
+            if total <= 1 {
+                return String::new();
+            }
+            let first_page = ctx.get_string("pagination_base")
+                .or_else(|| ctx.get_string("page.output_path"))
+                .unwrap_or_else(|| global.cfg.index_file_name());
+            let window = args.first().and_then(|w| w.parse::<usize>().ok()).unwrap_or(2);
+
+            let url = |page: usize| global.relative_url(&global.public_path(&pagination_page_path(&first_page, page, &global.cfg.index_file_name())));
+            let mut nav = String::from("<nav class=\"pagination\">\n");
+            if current > 1 {
+                nav.push_str(&format!("<a class=\"prev\" rel=\"prev\" href=\"{}\">Previous</a>\n", url(current - 1)));
+            }
+            let mut last_shown = 0;
+            for page in 1..=total {
+                if page != 1 && page != total && page.abs_diff(current) > window {
+                    continue;
+                }
+                if page > last_shown + 1 {
+                    nav.push_str("<span class=\"gap\">&hellip;</span>\n");
+                }
+                if page == current {
+                    nav.push_str(&format!("<span class=\"current\" aria-current=\"page\">{}</span>\n", page));
+                } else {
+                    nav.push_str(&format!("<a href=\"{}\">{}</a>\n", url(page), page));
+                }
+                last_shown = page;
+            }
+            if current < total {
+                nav.push_str(&format!("<a class=\"next\" rel=\"next\" href=\"{}\">Next</a>\n", url(current + 1)));
+            }
+            nav + "</nav>"
+        });
+
+        // A visible banner naming the environment on non-production builds
+        self.register_function("env_banner", &|_, _, _, global| {
+            let Some(environment) = global.cfg.non_production_environment() else {
+                return String::new();
+            };
+            let message = global.cfg.env_banner_message.clone()
+                .unwrap_or_else(|| "{environment}".to_string())
+                .replace("{environment}", &environment.to_uppercase());
+            let class = global.cfg.env_banner_class.clone().unwrap_or_else(|| "env-banner".to_string());
+            global.cfg.env_banner_html.clone()
+                .unwrap_or_else(|| "<div class=\"{class}\">{message}</div>".to_string())
+                .replace("{class}", &class)
+                .replace("{message}", &message)
+        });
+
+        // The value of an environment variable at build time, empty when unset
+        self.register_function("env", &|args, _, _, _| {
+            match args.first() {
+                Some(name) => std::env::var(name).unwrap_or_default(),
+                None => {
+                    eprintln!("Warning: env needs a variable name");
+                    String::new()
+                }
+            }
+        });
+
         self.register_function("image_html", &|_, _, ctx, global| {
             if let Some(url) = ctx.borrow().get_string("image") {
                 let url = global.relative_url(&url);
@@ -524,24 +1530,67 @@ impl GlobalContext {
 
         self.register_function(
             "list_md",
-            &|args, block, ctx, global| {
-                let path = args.get(0).expect("list_md requires a path argument");
-                let path = global.cfg.relative_to_config_path(&PathBuf::from(path));
-                let template_name = args.get(1); // Optional template name
+            &|args, _, ctx, global| {
+                let path = args.first().expect("list_md requires a path argument");
+                // Optional layout each entry is rendered with instead of its own; "" keeps theirs
+                let template_name = args.get(1).map(|name| name.trim()).filter(|name| !name.is_empty());
+                // Optional "field" or "field:desc" sort spec, otherwise file name order
+                let sort_spec = args.get(2).map(|spec| spec.trim()).filter(|spec| !spec.is_empty());
+                let limit = list_md_limit(args);
 
                 // println!("called list_md with {} and {:?}", path, template_name);
                 
                 let mut items = vec![];
                 
-                // Read directory and process markdown files
-                if let Ok(entries) = fs::read_dir(path) {
-                    for entry in entries.filter_map(|e| e.ok()) {
-                        items.push(
-                            match global.build_page(entry.path().to_str().unwrap()) {
-                                Ok(f) => f,
-                                Err(e) => Rc::new(TemplateNode::StringContent(format!("error: {:?}", e))),
-                            }
-                        );
+                // Read directory and process markdown files, keeping the listing page's site strings
+                let page_site_strings = global.page_site_strings.clone();
+                for entry in global.list_md_entries(path) {
+                    items.push(
+                        match global.build_page(entry.to_str().unwrap()) {
+                            Ok(f) => f,
+                            Err(e) => Rc::new(TemplateNode::StringContent(format!("error: {:?}", e))),
+                        }
+                    );
+                }
+                global.page_site_strings = page_site_strings;
+
+                if let Some(spec) = sort_spec {
+                    sort_by_front_matter(&mut items, spec, |item| match &**item {
+                        TemplateNode::Page { front_matter, .. } => Some(front_matter),
+                        _ => None,
+                    });
+                }
+                items.truncate(limit.unwrap_or(usize::MAX));
+
+                // On a paginated listing only this page's slice of the entries is rendered
+                let slice = {
+                    let ctx = ctx.borrow();
+                    let page_size = ctx.get_string("paginate").and_then(|size| global.pagination_size(&size));
+                    let page_number = ctx.get_string("page_number").and_then(|n| n.trim().parse::<usize>().ok());
+                    page_size.zip(page_number)
+                };
+                if let Some((page_size, page_number)) = slice {
+                    items = items.into_iter()
+                        .skip(page_number.saturating_sub(1) * page_size)
+                        .take(page_size)
+                        .collect();
+                }
+
+                if let Some(template_name) = template_name {
+                    match global.get_layout(template_name) {
+                        Ok(layout) => {
+                            items = items.into_iter().map(|item| match &*item {
+                                TemplateNode::Page { path, front_matter, content_node, output_path, .. } => TemplateNode::new_page(
+                                    path.clone(),
+                                    front_matter.clone(),
+                                    content_node.clone(),
+                                    output_path.clone(),
+                                    Some(layout.clone()),
+                                ),
+                                _ => item,
+                            }).collect();
+                        }
+                        Err(e) => eprintln!("Warning: list_md {} keeps each entry's own layout: {}", path, e),
                     }
                 }
                 
@@ -552,12 +1601,171 @@ impl GlobalContext {
             },
         );
 
+        self.register_function(
+            "children",
+            &|args, _, ctx, global| {
+                let Some(current) = ctx.borrow().get_string("page.source_path") else {
+                    return String::new();
+                };
+                let Some(dir) = Path::new(&current).parent() else {
+                    return String::new();
+                };
+
+                let mut children = global.pages.iter()
+                    .filter(|p| p.source_path != current && Path::new(&p.source_path).starts_with(dir))
+                    .filter(|p| !is_draft(&p.front_matter))
+                    .collect::<Vec<_>>();
+                if children.is_empty() {
+                    return String::new();
+                }
+
+                let sort_spec = args.first().cloned()
+                    .or_else(|| global.cfg.sort_by.clone())
+                    .unwrap_or_else(|| "title".to_string());
+                sort_pages(&mut children, &sort_spec);
+
+                children.iter().fold(String::from("<ul>\n"), |mut output, page| {
+                    output.push_str(&format!(
+                        "<li><a href=\"{}\">{}</a></li>\n",
+                        global.page_url(&page.output_path),
+                        escape_xml(&page.front_matter.get("title").map(ToString::to_string).unwrap_or_default())
+                    ));
+                    output
+                }) + "</ul>"
+            },
+        );
+
+        self.register_function(
+            "posts_by_year",
+            &|args, _, _, global| {
+                let limit = args.iter().find_map(|a| a.parse::<usize>().ok());
+                let include_undated = args.iter().any(|a| a == "undated");
+
+                let mut years: std::collections::BTreeMap<i32, Vec<(DateTime<Utc>, &PageInfo)>> = Default::default();
+                let mut undated = vec![];
+                for page in global.posts() {
+                    match page.front_matter.get("date").and_then(FrontMatterValue::as_str).and_then(parse_date) {
+                        Some(date) => years.entry(date.year()).or_default().push((date, page)),
+                        None => undated.push(page),
+                    }
+                }
+
+                let link = |page: &PageInfo| format!(
+                    "<li><a href=\"{}\">{}</a></li>\n",
+                    global.page_url(&page.output_path),
+                    escape_xml(&page.front_matter.get("title").map(ToString::to_string).unwrap_or_default())
+                );
+
+                let mut groups = years.into_iter()
+                    .rev()
+                    .take(limit.unwrap_or(usize::MAX))
+                    .map(|(year, mut posts)| {
+                        posts.sort_by_key(|(date, _)| std::cmp::Reverse(*date));
+                        (year.to_string(), posts.into_iter().map(|(_, p)| link(p)).collect::<String>())
+                    })
+                    .collect::<Vec<_>>();
+                if include_undated && !undated.is_empty() {
+                    groups.push(("Undated".to_string(), undated.into_iter().map(link).collect()));
+                }
+
+                if groups.is_empty() {
+                    return String::new();
+                }
+                groups.iter().fold(String::from("<ul class=\"posts-by-year\">\n"), |mut output, (year, links)| {
+                    output.push_str(&format!("<li><h3>{}</h3>\n<ul>\n{}</ul>\n</li>\n", year, links));
+                    output
+                }) + "</ul>"
+            },
+        );
+
+        // Renders a YAML sequence of mappings as a table; extra args pick the columns and their order
+        self.register_function(
+            "data_table",
+            &|args, _, _, global| {
+                let Some(path) = args.first() else {
+                    eprintln!("Warning: data_table requires a path argument");
+                    return String::new();
+                };
+                let full_path = global.cfg.relative_to_config_path(&PathBuf::from(path));
+                let rows = match global.load_yaml_data_merge_env_variant(full_path.to_str().unwrap()) {
+                    Ok(Value::Sequence(rows)) => rows,
+                    Ok(Value::Null) => vec![],
+                    Ok(_) => {
+                        eprintln!("Warning: data_table expects {} to be a list of mappings", path);
+                        return String::new();
+                    }
+                    Err(e) => {
+                        eprintln!("Warning: data_table could not load {}: {}", path, e);
+                        return String::new();
+                    }
+                };
+                let rows = rows.iter().filter_map(Value::as_mapping).collect::<Vec<_>>();
+                if rows.is_empty() && global.cfg.data_table_hide_empty.unwrap_or(false) {
+                    return String::new();
+                }
+
+                let mut columns = args[1..].iter()
+                    .flat_map(|a| a.split(','))
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty())
+                    .collect::<Vec<_>>();
+                if columns.is_empty() {
+                    for row in &rows {
+                        for key in row.keys().filter_map(Value::as_str) {
+                            if !columns.iter().any(|c| c == key) {
+                                columns.push(key.to_string());
+                            }
+                        }
+                    }
+                }
+
+                let mut output = String::from("<table>\n<thead>\n<tr>");
+                for column in &columns {
+                    output.push_str(&format!("<th>{}</th>", escape_xml(column)));
+                }
+                output.push_str("</tr>\n</thead>\n<tbody>\n");
+                for row in rows {
+                    output.push_str("<tr>");
+                    for column in &columns {
+                        let cell = match row.get(column.as_str()) {
+                            None | Some(Value::Null) => String::new(),
+                            Some(Value::String(s)) => s.clone(),
+                            Some(Value::Bool(b)) => b.to_string(),
+                            Some(Value::Number(n)) => n.to_string(),
+                            Some(other) => serde_yaml::to_string(other).unwrap_or_default().trim().to_string(),
+                        };
+                        output.push_str(&format!("<td>{}</td>", escape_xml(&cell)));
+                    }
+                    output.push_str("</tr>\n");
+                }
+                output + "</tbody>\n</table>"
+            },
+        );
+
+        // Number of items in a json_data sequence, or in the posts or pages collections
+        self.register_function(
+            "count",
+            &|args, _, ctx, global| {
+                let Some(key) = args.first() else {
+                    return "0".to_string();
+                };
+                if let Some(items) = ctx.borrow().get_json_data(key).as_ref().and_then(Value::as_sequence) {
+                    return items.len().to_string();
+                }
+                match key.as_str() {
+                    "posts" => global.posts().len().to_string(),
+                    "pages" => global.pages.iter().filter(|p| !is_draft(&p.front_matter)).count().to_string(),
+                    _ => "0".to_string(),
+                }
+            },
+        );
+
         self.register_function(
             "json_list",
             &|args, block, ctx, _| {
                 let items_key = "items".to_string();
                 let key = args.first().unwrap_or(&items_key);
-                ctx.borrow().json_data.get(key)
+                ctx.borrow().get_json_data(key).as_ref()
                     .and_then(Value::as_sequence)
                     .map(|items| {
                         block.map_or_else(|| {
@@ -586,59 +1794,215 @@ impl GlobalContext {
     fn register_function(&mut self, name: &str, func: &'static TemplateFunc) {
         self.functions.insert(name.to_string(), Rc::new(func));
     }
+
+    // Transforms run in registration order on each page's final HTML, before it is written
+    pub fn with_default_post_render_transforms(&mut self) -> &mut Self {
+        // Preview builds include unpublished pages but keep them out of search engines
+        self.register_post_render_transform(
+            "preview_noindex",
+            &|html, ctx, global| {
+                if global.cfg.preview.unwrap_or(false) && is_unpublished(&ctx.borrow().strings) {
+                    insert_before_closing_tag(&html, "head", "<meta name=\"robots\" content=\"noindex\">\n")
+                } else {
+                    html
+                }
+            },
+        );
+
+        self.register_post_render_transform(
+            "head_snippet",
+            &|html, _, global| match &global.head_snippet {
+                Some(snippet) => insert_before_closing_tag(&html, "head", snippet),
+                None => html,
+            },
+        );
+
+        self.register_post_render_transform(
+            "body_snippet",
+            &|html, _, global| match &global.body_snippet {
+                Some(snippet) => insert_before_closing_tag(&html, "body", snippet),
+                None => html,
+            },
+        );
+
+        // After the snippets, so links they add are rewritten too
+        self.register_post_render_transform(
+            "offline_bundle",
+            &|html, ctx, global| {
+                if !global.cfg.offline_bundle.unwrap_or(false) {
+                    return html;
+                }
+                match ctx.borrow().get_string("page.output_path") {
+                    Some(output_path) => global.relativize_site_links(&html, &output_path),
+                    None => html,
+                }
+            },
+        );
+
+        // Last, so everything the other transforms add is minified too
+        self.register_post_render_transform(
+            "minify_html",
+            &|html, _, global| {
+                if global.cfg.minify_html.unwrap_or(false) {
+                    minify_html(&html)
+                } else {
+                    html
+                }
+            },
+        );
+        self
+    }
+
+    fn register_post_render_transform(&mut self, name: &str, func: &'static PostRenderTransform) {
+        self.post_render_transforms.push((name.to_string(), Rc::new(func)));
+    }
+
+    pub fn apply_post_render_transforms(&self, html: String, context: TemplateContextPtr) -> String {
+        self.post_render_transforms.iter()
+            .fold(html, |html, (_, transform)| transform(html, context.clone(), self))
+    }
     
-    pub fn get_layout(&mut self, name: &str) -> Rc<TemplateNode> {
+    pub fn get_layout(&mut self, name: &str) -> Result<Rc<TemplateNode>, Box<dyn Error>> {
         // println!("get_layout {}", name);
         if let Some(layout) = self.layout_cache.get(name) {
-            return layout.clone();
+            self.page_dependencies.borrow_mut().extend(self.layout_files[name].iter().cloned());
+            return Ok(layout.clone());
         }
-        
-        let path = PathBuf::from("templates").join(format!("{}.tpl.html", name));
-        let path = self.cfg.relative_to_config_path(&path);
+
+        // Collect the files this layout reads on its own, so pages using the cached layout depend on them too
+        let outer_dependencies = self.page_dependencies.take();
+        let layout = self.load_layout(name);
+        let files = self.page_dependencies.replace(outer_dependencies);
+        self.page_dependencies.borrow_mut().extend(files.iter().cloned());
+        if layout.is_ok() {
+            self.layout_files.insert(name.to_string(), files);
+        }
+        layout
+    }
+
+    fn load_layout(&mut self, name: &str) -> Result<Rc<TemplateNode>, Box<dyn Error>> {
+        let path = self.find_template(&format!("{}.tpl.html", name))
+            .map_err(|e| format!("layout {} is missing: {}", name, e))?;
+        self.record_dependency(&path);
         let content = fs::read_to_string(&path)
-        .unwrap_or_else(|_| panic!("Failed to read template: {} at {}", name, path.to_str().unwrap()));
+            .map_err(|e| format!("Failed to read layout {} at {}: {}", name, path.display(), e))?;
         
         let (front_matter, html) = parse_front_matter(&content);
         let mut front_matter = parse_yaml_front_matter(front_matter).unwrap_or_default();
         if !front_matter.contains_key("layout") && name != "default" && name != "site" {
-            front_matter.insert("layout".to_string(), "default".to_string());
+            front_matter.insert("layout".to_string(), "default".to_string().into());
         }
 
-        Self::get_front_matter_json_data(&mut front_matter);
+        self.load_front_matter_json_data(&mut front_matter);
 
         // Check if this layout has a parent layout
-        let parent_layout = if let Some(layout_name) = front_matter.get("layout") {
+        let parent_layout = if let Some(layout_name) = front_matter.get("layout").and_then(FrontMatterValue::as_str) {
             if layout_name.is_empty() {
                 None
             } else {
-                Some(self.get_layout(&layout_name))
+                let parent = self.get_layout(layout_name)
+                    .map_err(|e| format!("layout {} extends {}, but {}", name, layout_name, e))?;
+                Some(parent)
             }
         } else {
             None
         };
     
         // Parse control blocks in the content
-        let content_node = self.parse_control_blocks(&html);
+        let content_node = self.parse_control_blocks(html);
         
         let layout = TemplateNode::new_layout(name.to_string(), front_matter, content_node, parent_layout);
         self.layout_cache.insert(name.to_string(), layout.clone());
-        layout
+        Ok(layout)
+    }
+    
+    // Parses a partial from the template directories for include, once per build
+    fn get_partial(&mut self, path: &str) -> Result<Rc<TemplateNode>, Box<dyn Error>> {
+        if let Some((partial, full_path)) = self.partial_cache.get(path) {
+            self.record_dependency(full_path);
+            return Ok(partial.clone());
+        }
+        let full_path = self.find_template(path)?;
+        self.record_dependency(&full_path);
+        let content = fs::read_to_string(&full_path)
+            .map_err(|e| format!("Failed to read {}: {}", full_path.display(), e))?;
+        let partial = self.parse_control_blocks(&content);
+        self.partial_cache.insert(path.to_string(), (partial.clone(), full_path));
+        Ok(partial)
+    }
+
+    // Fails with the problems template functions reported while rendering the last page, if any
+    fn take_render_errors(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.render_errors.is_empty() {
+            return Ok(());
+        }
+        Err(std::mem::take(&mut self.render_errors).join("; ").into())
+    }
+
+    // Notes a file the current page is built from, so incremental builds rebuild it when the file changes
+    fn record_dependency(&self, path: &Path) {
+        self.page_dependencies.borrow_mut().insert(path.to_path_buf());
+    }
+
+    // Hash of everything a page can depend on besides its own files: the config, site strings
+    // (except build_revision, which changes every commit) and every page's location and front matter
+    fn site_hash(&self) -> String {
+        let config = serde_yaml::to_value(&self.cfg).map(sort_yaml_mappings).unwrap_or_default();
+        let mut state = serde_yaml::to_string(&config).unwrap_or_default();
+        let site_strings = self.site_strings.iter()
+            .filter(|(key, _)| key.as_str() != "build_revision")
+            .collect::<BTreeMap<_, _>>();
+        state.push_str(&format!("{:?}", site_strings));
+        for page in &self.pages {
+            let front_matter = front_matter_strings(&page.front_matter).into_iter().collect::<BTreeMap<_, _>>();
+            state.push_str(&format!("{}\n{}\n{:?}\n", page.source_path, page.output_path.display(), front_matter));
+        }
+        content_hash(state.as_bytes())
+    }
+
+    // Looks for a template file in each template directory in order, earlier directories winning
+    pub fn find_template(&self, file_name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let candidates = self.cfg.template_search_dirs().into_iter()
+            .map(|dir| dir.join(file_name))
+            .collect::<Vec<_>>();
+
+        candidates.iter()
+            .find(|p| p.is_file())
+            .cloned()
+            .ok_or_else(|| format!(
+                "{} not found in any template directory (searched: {})",
+                file_name,
+                candidates.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+            ).into())
     }
     
-    pub fn load_site_data(&mut self) {
+    pub fn load_site_data(&mut self) -> Result<(), Box<dyn Error>> {
         let path = self.cfg.relative_to_config_path(&PathBuf::from("data/site.yaml"));
         let path = path.to_str().unwrap();
-        let site_yaml = self.load_yaml_data_merge_env_variant(path).expect(&format!("could not get {}", path));
+        let site_yaml = self.load_yaml_data_merge_env_variant(path)
+            .map_err(|e| format!("could not get {}: {}", path, e))?;
         if let Value::Mapping(mapping) = site_yaml {
-            self.load_site_data_from_yaml_mapping(mapping)
+            self.load_site_data_from_yaml_mapping(mapping)?;
         } else {
-            panic!("unsupported site yaml type")
+            return Err(format!("{} must be a mapping, found {}", path, yaml_type_name(&site_yaml)).into());
+        }
+        if let Some(site_url) = &self.cfg.site_url {
+            self.site_strings.insert("site.url".to_string(), site_url.clone());
+        }
+        if !self.site_strings.contains_key("site.url") {
+            eprintln!(
+                "Warning: no site.url in {} (or site_url in the config), so links are site-relative, e.g. /about.html; add one like site.url: https://www.example.com/",
+                path
+            );
         }
+        Ok(())
     }
 
     pub fn load_yaml_data_merge_env_variant(&self, path: &str)  -> Result<Value, Box<dyn Error>> {
+        self.record_dependency(Path::new(path));
         let primary = load_yaml_data(path)?;
         if let Some(path_env_secondary) = self.path_add_variant(path) {
+            self.record_dependency(Path::new(&path_env_secondary));
             // Only merge if variant file exists
             if Path::new(&path_env_secondary).exists() {
                 let secondary = load_yaml_data(&path_env_secondary)?;
@@ -673,17 +2037,103 @@ impl GlobalContext {
         None
     }
     
-    pub fn load_site_data_from_yaml_mapping(&mut self, mapping: serde_yaml::Mapping) {
+    pub fn load_site_data_from_yaml_mapping(&mut self, mapping: serde_yaml::Mapping) -> Result<(), Box<dyn Error>> {
+        let mut strings = HashMap::new();
+        self.load_site_data_entries("", &mapping, &mut strings)?;
+        self.site_strings.extend(strings);
+        Ok(())
+    }
+
+    // Nested mappings become dotted keys (social: { github: x } is social.github) and lists of
+    // scalars are comma-separated, like front matter
+    fn load_site_data_entries(&self, prefix: &str, mapping: &serde_yaml::Mapping, strings: &mut HashMap<String, String>) -> Result<(), Box<dyn Error>> {
         for (k, v) in mapping.iter() {
-            let v = if v.is_bool() {
-                v.as_bool().unwrap().to_string()
-            } else {
-                v.as_str().unwrap().to_string()
+            let Some(key) = k.as_str() else {
+                self.unsupported_site_data(&format!("{}{:?}", prefix, k), "non-string key")?;
+                continue;
+            };
+            let key = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+            let value = match v {
+                Value::Mapping(nested) => {
+                    self.load_site_data_entries(&key, nested, strings)?;
+                    continue;
+                }
+                Value::Null => None,
+                Value::Sequence(items) => items.iter()
+                    .map(|item| if item.is_null() { None } else { front_matter_scalar(item) })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|items| items.join(", ")),
+                other => front_matter_scalar(other),
             };
-            self.site_strings.insert(k.as_str().unwrap().to_string(), v);
+            match value {
+                Some(value) => {
+                    strings.insert(key, value);
+                }
+                None if v.is_sequence() => self.unsupported_site_data(&key, "sequence with null or nested items")?,
+                None => self.unsupported_site_data(&key, yaml_type_name(v))?,
+            }
+        }
+        Ok(())
+    }
+
+    // Skips the value with a warning, or fails under strict or unsupported_site_data: error
+    fn unsupported_site_data(&self, key: &str, type_name: &str) -> Result<(), Box<dyn Error>> {
+        let message = format!("site data {} has unsupported type {}", key, type_name);
+        let policy = self.cfg.unsupported_site_data.as_deref().map(str::trim);
+        if self.cfg.strict.unwrap_or(false) || policy == Some("error") {
+            return Err(message.into());
+        }
+        if policy.is_some_and(|p| p != "skip") {
+            eprintln!("Warning: unknown unsupported_site_data policy {}, expected skip or error", policy.unwrap_or_default());
         }
+        eprintln!("Warning: {}, skipping it", message);
+        Ok(())
     }
     
+    // A fixed build_revision or the build_revision_env variable skips asking git
+    fn get_build_revision(&self) -> String {
+        if let Some(revision) = &self.cfg.build_revision {
+            return revision.clone();
+        }
+        self.cfg.build_revision_env.as_ref()
+            .and_then(|name| std::env::var(name).ok())
+            .filter(|revision| !revision.trim().is_empty())
+            .unwrap_or_else(Self::get_git_revision)
+    }
+
+    // When a file was last committed, or None when git isn't available or the file is untracked
+    fn git_commit_date(&self, path: &Path) -> Option<DateTime<Utc>> {
+        let dates = self.git_commit_dates.get_or_init(|| self.load_git_commit_dates());
+        dates.get(&path.canonicalize().ok()?).copied()
+    }
+
+    // One git log over the content directory, newest first, so each file keeps its latest commit date
+    fn load_git_commit_dates(&self) -> HashMap<PathBuf, DateTime<Utc>> {
+        let content_path = self.cfg.full_content_path();
+        let output = Command::new("git")
+            .arg("-C").arg(&content_path)
+            .args(["-c", "core.quotePath=false", "log", "--relative", "--name-only", "--format=%x00%cI", "--", "."])
+            .output()
+            .ok()
+            .filter(|output| output.status.success());
+        let Some(output) = output else {
+            return HashMap::new();
+        };
+
+        let mut dates = HashMap::new();
+        let mut commit_date = None;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(date) = line.strip_prefix('\0') {
+                commit_date = DateTime::parse_from_rfc3339(date.trim()).ok().map(|d| d.with_timezone(&Utc));
+            } else if let (Some(date), false) = (commit_date, line.trim().is_empty()) {
+                if let Ok(path) = content_path.join(line.trim()).canonicalize() {
+                    dates.entry(path).or_insert(date);
+                }
+            }
+        }
+        dates
+    }
+
     fn get_git_revision() -> String {
         fn try_git_command(args: &[&str]) -> Option<String> {
             Command::new("git")
@@ -707,14 +2157,21 @@ impl GlobalContext {
     }
     
 
-    fn parse_control_blocks(&self, content: &str) -> Rc<TemplateNode> {
+    fn parse_control_blocks(&mut self, content: &str) -> Rc<TemplateNode> {
         let mut nodes = Vec::new();
         let mut remaining = content;
         
         while let Some(open_pos) = remaining.find("{{") {
             let before = &remaining[..open_pos];
+            // \{{ is a literal {{, kept out of parsing and substitution
+            if let Some(before) = before.strip_suffix('\\') {
+                nodes.push(TemplateNode::StringContent(unescape_close_delimiters(before)));
+                nodes.push(TemplateNode::StringContent(protect_raw_text("{{")));
+                remaining = &remaining[open_pos + 2..];
+                continue;
+            }
             if !before.is_empty() {
-                nodes.push(TemplateNode::StringContent(before.to_string()));
+                nodes.push(TemplateNode::StringContent(unescape_close_delimiters(before)));
             }
             
             let close_pos = remaining[open_pos..].find("}}").unwrap() + open_pos;
@@ -723,22 +2180,29 @@ impl GlobalContext {
             remaining = &remaining[close_pos+2..];
             
             match tag.split_whitespace().collect::<Vec<_>>().as_slice() {
-                ["if", condition] => {
-                    let (inner_content, new_remaining) = Self::parse_block_content(remaining, "endif");
+                [keyword @ ("if" | "if_env" | "if_date_between"), condition @ ..] if !condition.is_empty() => {
+                    let end_tag = if *keyword == "if_env" { "endif_env" } else { "endif" };
+                    let (inner_content, new_remaining) = Self::parse_block_content(remaining, end_tag);
                     remaining = new_remaining;
                     
                     // Split into if and else parts if needed
-                    let (true_content, false_content) = match inner_content.split_once("{{ else }}") {
-                        Some((true_part, false_part)) => (true_part, Some(false_part)),
-                        None => (inner_content, None),
-                    };
+                    let (true_content, elif_contents, false_content) = Self::split_if_branches(inner_content);
                     
                     let true_node = self.parse_control_blocks(true_content);
+                    let elif_branches = elif_contents.into_iter()
+                        .map(|(condition, content)| (condition, self.parse_control_blocks(content)))
+                        .collect();
                     let false_node = false_content.map(|c| self.parse_control_blocks(c));
                     
+                    let condition = match *keyword {
+                        "if_env" => format!("env {}", condition.join(" ")),
+                        "if_date_between" => format!("date_between {}", condition.join(" ")),
+                        _ => condition.join(" "),
+                    };
                     nodes.push(TemplateNode::IfBlock {
-                        condition: condition.to_string(),
+                        condition,
                         true_branch: true_node,
+                        elif_branches,
                         false_branch: false_node,
                     });
                 },
@@ -749,6 +2213,29 @@ impl GlobalContext {
                     }
                     // Skip this token and continue parsing
                 },
+                ["elseif", ..] => {
+                    eprintln!("Warning: found elseif without matching if in content: {:?}", tag);
+                },
+                // Everything up to endraw is literal text, with no tags or substitutions in it
+                ["raw"] => {
+                    let (inner_content, new_remaining) = Self::parse_block_content(remaining, "endraw");
+                    remaining = new_remaining;
+                    nodes.push(TemplateNode::StringContent(protect_raw_text(inner_content)));
+                },
+                ["endraw"] => {
+                    eprintln!("Warning: found endraw without matching raw in content");
+                },
+                ["macro", ..] => {
+                    let (inner_content, new_remaining) = Self::parse_block_content(remaining, "endmacro");
+                    remaining = new_remaining;
+                    match Self::parse_macro_signature(&tag["macro".len()..]) {
+                        Some((name, params)) => {
+                            let body = self.parse_control_blocks(inner_content);
+                            self.macros.insert(name, Rc::new(TemplateMacro { params, body }));
+                        }
+                        None => eprintln!("Warning: invalid macro definition {:?}, expected macro name(param, ...)", tag),
+                    }
+                },
                 ["foreach", key, "as", item_name] => {
                     let (inner_content, new_remaining) = Self::parse_block_content(remaining, "endforeach");
                     remaining = new_remaining;
@@ -761,13 +2248,20 @@ impl GlobalContext {
                 },
                 _ => {
                     match Self::parse_function_call(tag) {
-                        Some((name, args)) if self.functions.contains_key(name) => {
+                        Some((name, args)) if self.functions.contains_key(&name) => {
                             nodes.push(TemplateNode::Func {
-                                name: name.to_string(),
-                                args: args.iter().map(|s| s.to_string()).collect(),
+                                name,
+                                args,
                                 block_content: None,
                             });
                         }
+                        Some((name, args)) => {
+                            nodes.push(TemplateNode::MacroCall {
+                                name,
+                                args,
+                                tag: complete_tag.to_string(),
+                            });
+                        }
                         _ => {
                             nodes.push(TemplateNode::StringContent(complete_tag.to_string()));
                         }
@@ -777,154 +2271,901 @@ impl GlobalContext {
         }
         
         if !remaining.is_empty() {
-            nodes.push(TemplateNode::StringContent(remaining.to_string()));
+            nodes.push(TemplateNode::StringContent(unescape_close_delimiters(remaining)));
         }
         
         Rc::new(TemplateNode::Composite(nodes))
     }
 
+    // Parses "name(a, b)" (parentheses optional when there are no parameters)
+    fn parse_macro_signature(signature: &str) -> Option<(String, Vec<String>)> {
+        let signature = signature.trim();
+        let (name, params) = match signature.split_once('(') {
+            Some((name, rest)) => (name.trim(), rest.trim().strip_suffix(')')?),
+            None => (signature, ""),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return None;
+        }
+        let params = params.split(',')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(String::from)
+            .collect();
+        Some((name.to_string(), params))
+    }
+
+    // Splits content at the end tag that closes the current block, skipping over nested blocks
+    // of the same kind, e.g. the inner {{ if }}...{{ endif }} inside an outer if
     fn parse_block_content<'a>(content: &'a str, end_tag: &str) -> (&'a str, &'a str) {
-        let end_pattern = format!("{{{{ {end_tag} }}}}");
-        let end_pos = content.find(&end_pattern).unwrap_or(content.len());
-        (&content[..end_pos], &content[end_pos + end_pattern.len()..])
+        let openers: &[&str] = match end_tag {
+            "endif" => &["if", "if_date_between"],
+            "endif_env" => &["if_env"],
+            "endforeach" => &["foreach"],
+            "endmacro" => &["macro"],
+            _ => &[],
+        };
+        match find_block_tag(content, &[end_tag], openers, &[end_tag]) {
+            Some((start, end)) => (&content[..start], &content[end..]),
+            None => (content, ""),
+        }
+    }
+
+    // Splits an if block's content at its own {{ elseif condition }} and {{ else }} tags, ignoring
+    // any inside nested if blocks, into the if content, each elseif's condition and content, and
+    // the else content
+    fn split_if_branches(content: &str) -> (&str, Vec<(String, &str)>, Option<&str>) {
+        let mut if_content = None;
+        let mut elif_branches = vec![];
+        let mut condition: Option<String> = None;
+        let mut remaining = content;
+        let branch_tags = ["elseif", "else"];
+        while let Some((start, end)) = find_block_tag(remaining, &branch_tags, &["if", "if_env", "if_date_between"], &["endif", "endif_env"]) {
+            let branch = &remaining[..start];
+            match condition.take() {
+                Some(condition) => elif_branches.push((condition, branch)),
+                None => if_content = Some(branch),
+            }
+            let tag = remaining[start + 2..end - 2].trim();
+            remaining = &remaining[end..];
+            match tag.strip_prefix("elseif") {
+                Some(elif_condition) => condition = Some(elif_condition.trim().to_string()),
+                None => return (if_content.unwrap_or_default(), elif_branches, Some(remaining)),
+            }
+        }
+        match condition {
+            Some(condition) => elif_branches.push((condition, remaining)),
+            None => if_content = Some(remaining),
+        }
+        (if_content.unwrap_or_default(), elif_branches, None)
     }
 
-    fn parse_function_call(tag: &str) -> Option<(&str, Vec<&str>)> {
-        let mut parts = tag.split_whitespace();
+    // Splits a tag into a function name and arguments. Double-quoted arguments may contain spaces;
+    // markdown's smart quotes and &quot; entities count as plain quotes
+    fn parse_function_call(tag: &str) -> Option<(String, Vec<String>)> {
+        let tag = tag.replace("&quot;", "\"").replace(['\u{201C}', '\u{201D}'], "\"");
+        let mut parts = split_quoted_args(&tag).into_iter();
         parts.next().map(|name| (name, parts.collect()))
     }
 
-    fn build_page(
-        &mut self,
-        path: &str,
-    ) -> Result<Rc<TemplateNode>, Box<dyn Error>> {
+    // Reads a markdown file, returning its front matter (with defaults filled in) and body
+    fn load_page_source(&self, path: &str) -> Result<(FrontMatter, String), Box<dyn Error>> {
         let content = fs::read_to_string(path)?;
         let (front_matter, markdown) = parse_front_matter(&content);
         let mut front_matter = parse_yaml_front_matter(front_matter)?;
         
         // Set defaults
         // println!("page {} front_matter.keys: {}", path, front_matter.keys().into_iter().cloned().collect::<Vec<String>>().join(", "));
-        if !front_matter.contains_key("layout") {
-            front_matter.insert("layout".to_string(), "default".to_string());
-        }
+        let layout = self.select_layout(path, &front_matter);
+        front_matter.insert("layout".to_string(), layout.into());
         if !front_matter.contains_key("title") {
             front_matter.insert("title".to_string(), 
-            Path::new(path).file_stem().unwrap().to_string_lossy().into_owned());
+            Path::new(path).file_stem().unwrap().to_string_lossy().into_owned().into());
         }
 
-        Self::get_front_matter_json_data(&mut front_matter);
-        
-        // Convert markdown to HTML
-        let mut html_content = String::new();
-        let parser = pulldown_cmark::Parser::new_ext(markdown, Options::all())
-            .map(|event| match event {
-                // Rewrite links
-                Event::Start(Tag::Link { dest_url, link_type, title, id }) => {
-                    // println!("found link {}", dest_url);
-                    let new_dest = self.relative_url(dest_url.as_ref());
-                    Event::Start(Tag::Link { link_type, dest_url: new_dest.into(), title, id })
-                }
-                // Rewrite images
-                Event::Start(Tag::Image { dest_url, link_type, title, id }) => {
-                    // println!("found img {}", dest_url);
-                    let new_dest = self.relative_url(dest_url.as_ref());
-                    Event::Start(Tag::Image { link_type, dest_url: new_dest.into(), title, id })
-                }
-                // Pass through other events unchanged
-                _ => event,
-            });
-        html::push_html(&mut html_content, parser);
-        
-        // Parse control blocks in the content
-        let content_node = self.parse_control_blocks(&html_content);
-        
-        // Create output path
-        let output_path = self.cfg.full_output_path()
-            .join(file_path_stem(&self.cfg.full_input_path(), path))
-            .with_extension("html");
+        Ok((front_matter, markdown.to_string()))
+    }
 
-        // println!("output_path: {:?}", output_path);
-        
-        // Get the layout hierarchy
-        let layout = if let Some(layout_name) = front_matter.get("layout") {
-            if layout_name.is_empty() {
-                None
-            } else {
-                Some(self.get_layout(layout_name))
+    // Picks a page's layout: its own layout (with {{ key }} expressions filled from front matter),
+    // then the type_layouts entry for its type, then default
+    fn select_layout(&self, path: &str, front_matter: &FrontMatter) -> String {
+        if let Some(layout) = front_matter.get("layout").and_then(FrontMatterValue::as_str) {
+            if !layout.contains("{{") {
+                return layout.to_string();
             }
-        } else {
-            None
-        };
-        
-        // Create the page with the layout as parent
-        Ok(TemplateNode::new_page(
-            path.to_string(),
-            front_matter,
-            content_node,
-            output_path,
-            layout,
-        ))
+            match fill_front_matter_expressions(layout, front_matter) {
+                Some(resolved) if self.find_template(&format!("{}.tpl.html", resolved)).is_ok() => return resolved,
+                Some(resolved) => eprintln!("Warning: layout {} for {} does not exist, falling back", resolved, path),
+                None => eprintln!("Warning: layout {} for {} refers to missing front matter, falling back", layout, path),
+            }
+        }
+
+        front_matter.get("type").and_then(FrontMatterValue::as_str)
+            .and_then(|page_type| self.cfg.type_layouts.as_ref()?.get(page_type.trim()).cloned())
+            .unwrap_or_else(|| "default".to_string())
+    }
+
+    // Collects front matter and output locations for every page before rendering starts
+    fn collect_pages(&mut self, paths: &[String]) -> Result<(), Box<dyn Error>> {
+        self.pages = paths.iter()
+            .map(|path| {
+                let (front_matter, _) = self.load_page_source(path)?;
+                Ok(PageInfo {
+                    source_path: path.clone(),
+                    output_path: self.page_output_path(path, &front_matter)?,
+                    front_matter,
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        self.homepage = self.find_homepage();
+        Ok(())
     }
 
-    fn get_front_matter_json_data(front_matter: &mut HashMap<String, String>) {
-        if let Some(json_path) = &front_matter.get("json_data") {
-            let json_path = std::env::current_dir().unwrap().join(json_path).to_string_lossy().to_string();
-            if let Ok(json_data) = load_yaml_data(json_path.as_str()) {
-                // todo: implement returning the json/yaml and put it into current context
-                // front_matter.insert("items".to_string(), format!("{:?}", json_data));
-                // front_matter.insert_node("json_list".to_string(), TemplateNode::Json("items"));
+    // Source path of the homepage: the page named by the homepage config, else the page with
+    // homepage: true front matter, else the root index page
+    fn find_homepage(&self) -> Option<String> {
+        let mut flagged = self.pages.iter()
+            .filter(|p| p.front_matter.get("homepage").and_then(FrontMatterValue::as_str).is_some_and(|v| matches!(v.trim(), "true" | "yes")))
+            .map(|p| p.source_path.clone())
+            .collect::<Vec<_>>();
+        flagged.sort();
+
+        if let Some(homepage) = &self.cfg.homepage {
+            let path = self.cfg.full_content_path().join(homepage);
+            let Some(page) = self.pages.iter().find(|p| Path::new(&p.source_path) == path) else {
+                eprintln!("Warning: homepage {} does not exist", path.display());
+                return None;
+            };
+            for other in flagged.iter().filter(|p| **p != page.source_path) {
+                eprintln!("Warning: {} is marked as the homepage, but the config names {}", other, page.source_path);
             }
+            return Some(page.source_path.clone());
         }
-    }
 
-    fn relative_url(&self, path: &str) -> String {
-        if has_protocol(path) || path.starts_with('#') {
-            return path.to_string();
+        if flagged.len() > 1 {
+            eprintln!("Warning: several pages are marked as the homepage ({}), using {}", flagged.join(", "), flagged[0]);
         }
-        
-        let mut base = self.site_strings.get("site.url").unwrap().to_owned();
-        base = base.trim_end_matches('/').to_string();
-        let path = path.trim_start_matches('/').to_string();
-        format!("{}/{}", base, path)
+        flagged.into_iter().next().or_else(|| {
+            let root_index = self.cfg.full_output_path().join(self.cfg.index_file_name());
+            self.pages.iter()
+                .find(|p| p.output_path == root_index)
+                .map(|p| p.source_path.clone())
+        })
     }
 
-    fn load_robots_config(&self) -> Result<Option<RobotsConfig>, Box<dyn std::error::Error>> {
-        let config_path = self.cfg.relative_to_config_path(&PathBuf::from("data/robots_config.yaml"));
-        if fs::exists(&config_path)? {
-            let mut file = File::open(config_path)?;
-            let mut contents = String::new();
-            file.read_to_string(&mut contents)?;
-            
-            let config: RobotsConfig = serde_yaml::from_str(&contents)?;
-            Ok(Some(config))
-        } else {
-            Ok(None)
+    // Each distinct value of a list front matter key (tags, categories) across published pages,
+    // sorted alphabetically, with the pages using it
+    fn taxonomy_terms(&self, key: &str) -> Vec<(String, Vec<&PageInfo>)> {
+        let mut terms: HashMap<String, (String, Vec<&PageInfo>)> = HashMap::new();
+        for page in self.pages.iter().filter(|p| !is_draft(&p.front_matter)) {
+            let Some(values) = page.front_matter.get(key) else {
+                continue;
+            };
+            for term in values.items() {
+                terms.entry(slugify(term))
+                    .or_insert_with(|| (term.to_string(), vec![]))
+                    .1.push(page);
+            }
         }
+        let mut terms = terms.into_values().collect::<Vec<_>>();
+        terms.sort_by_key(|(term, _)| term.to_lowercase());
+        terms
     }
-}
 
-impl SitemapXmlNode {
-    // Creates a new sitemap entry with required URL
-    pub fn new(loc: String) -> Self {
-        Self {
-            loc,
-            lastmod: None,
-            changefreq: None,
-            priority: None,
-            alternates: Vec::new(),
+    // Links to each tag or category index page, as a <ul> or, given "inline" or a separator, a line
+    fn render_taxonomy_links(&self, key: &str, url_pattern: &str, separator: Option<&str>) -> String {
+        let links = self.taxonomy_terms(key).into_iter()
+            .map(|(term, _)| format!(
+                "<a href=\"{}\">{}</a>",
+                self.relative_url(&url_pattern.replace("{slug}", &slugify(&term))),
+                escape_xml(&term)
+            ))
+            .collect::<Vec<_>>();
+        if links.is_empty() {
+            return String::new();
+        }
+        match separator {
+            Some("inline") => links.join(", "),
+            Some(separator) => links.join(separator),
+            None => links.iter().fold(String::from("<ul>\n"), |mut output, link| {
+                output.push_str(&format!("<li>{}</li>\n", link));
+                output
+            }) + "</ul>",
         }
     }
-    
-    // Sets the last modification date
-    pub fn with_lastmod(mut self, lastmod: DateTime<Utc>) -> Self {
-        self.lastmod = Some(lastmod);
-        self
+
+    // Published pages under posts_dir, or every published page when it isn't set
+    fn posts(&self) -> Vec<&PageInfo> {
+        let posts_dir = self.cfg.posts_dir.as_ref()
+            .map(|dir| self.cfg.full_content_path().join(dir));
+        self.pages.iter()
+            .filter(|p| posts_dir.as_ref().is_none_or(|dir| Path::new(&p.source_path).starts_with(dir)))
+            .filter(|p| !is_draft(&p.front_matter))
+            .collect()
     }
-    
-    // Sets the change frequency
-    pub fn with_changefreq(mut self, changefreq: ChangeFrequency) -> Self {
-        self.changefreq = Some(changefreq);
-        self
+
+    // Reports markdown links to pages or files missing from the output, and #fragments that
+    // don't match a heading ID on the linked page. Returns how many problems were found.
+    fn check_links(&self) -> usize {
+        let output_base = self.cfg.full_output_path();
+        let mut sources = self.page_links.keys().collect::<Vec<_>>();
+        sources.sort();
+
+        let mut broken = 0;
+        for source in sources {
+            let Some(page) = self.pages.iter().find(|p| &p.source_path == source) else {
+                continue;
+            };
+            for link in &self.page_links[source] {
+                if has_protocol(link) || link.starts_with("//") {
+                    continue;
+                }
+                let (target, fragment) = link.split_once('#').unwrap_or((link, ""));
+                let target = target.split('?').next().unwrap_or_default();
+
+                // Links are resolved from the site root, as relative_url does
+                let target_path = if target.is_empty() {
+                    Some(page.output_path.clone())
+                } else {
+                    let path = output_base.join(target.trim_start_matches('/'));
+                    let index_file = self.cfg.index_file_name();
+                    let candidates = if target.ends_with('/') {
+                        vec![path.join(index_file)]
+                    } else {
+                        vec![path.clone(), path.with_extension("html"), path.join(index_file)]
+                    };
+                    candidates.into_iter()
+                        .find(|candidate| self.page_anchors.contains_key(candidate) || candidate.is_file())
+                };
+
+                match target_path {
+                    None => {
+                        eprintln!("Warning: broken link in {}: {}", source, link);
+                        broken += 1;
+                    }
+                    Some(target_path) if !fragment.is_empty() => {
+                        if let Some(anchors) = self.page_anchors.get(&target_path) {
+                            if !anchors.iter().any(|anchor| anchor == fragment) {
+                                let linked_page = if target.is_empty() { "the same page" } else { target };
+                                eprintln!("Warning: broken link in {}: {} has no #{} anchor", source, linked_page, fragment);
+                                broken += 1;
+                            }
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        broken
+    }
+
+    // (name, url) for the homepage, each directory above the page and the page itself, named by
+    // the title of the directory's index page when there is one
+    fn breadcrumb_trail(&self, source_path: &str) -> Vec<(String, String)> {
+        let Some(page) = self.pages.iter().find(|p| p.source_path == source_path) else {
+            return vec![];
+        };
+        let output_base = self.cfg.full_output_path();
+        let index_file = self.cfg.index_file_name();
+        let relative_path = page.output_path.strip_prefix(&output_base).unwrap_or(&page.output_path);
+        let title_of = |output_path: &Path| self.pages.iter()
+            .find(|p| p.output_path == output_path)
+            .and_then(|p| p.front_matter.get("title").map(ToString::to_string));
+
+        let home = self.homepage.as_ref()
+            .and_then(|home| self.pages.iter().find(|p| &p.source_path == home))
+            .map_or_else(|| output_base.join(&index_file), |p| p.output_path.clone());
+        let mut trail = vec![(
+            title_of(&home).unwrap_or_else(|| "Home".to_string()),
+            self.page_url(&home),
+        )];
+        if page.output_path == home {
+            return trail;
+        }
+        let mut dir = output_base.clone();
+        if let Some(parent) = relative_path.parent() {
+            for segment in parent.iter() {
+                dir = dir.join(segment);
+                let index = dir.join(&index_file);
+                trail.push((
+                    title_of(&index).unwrap_or_else(|| segment.to_string_lossy().into_owned()),
+                    self.page_url(&index),
+                ));
+            }
+        }
+        if relative_path.file_name().is_some_and(|name| name != index_file.as_str()) {
+            trail.push((
+                page.front_matter.get("title").map(ToString::to_string).unwrap_or_default(),
+                self.page_url(&page.output_path),
+            ));
+        }
+        trail
+    }
+
+    // Output file for a page, mirroring its source location unless its front matter sets an
+    // output_dir (relative to the output root) to place it elsewhere
+    fn page_output_path(&self, path: &str, front_matter: &FrontMatter) -> Result<PathBuf, Box<dyn Error>> {
+        let output_base = self.cfg.full_output_path();
+        let relative_path = PathBuf::from(file_path_stem(&self.cfg.full_content_path(), path))
+            .with_extension("html");
+
+        let relative_path = match front_matter.get("output_dir").and_then(FrontMatterValue::as_str) {
+            Some(dir) => {
+                let dir = Path::new(dir.trim().trim_start_matches('/'));
+                if dir.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+                    return Err(format!("output_dir {} in {} must stay inside the output directory", dir.display(), path).into());
+                }
+                dir.join(relative_path.file_name().unwrap())
+            }
+            None => relative_path,
+        };
+
+        // With pretty_urls "about.html" is written as "about/index.html"; index pages stay as they are
+        let is_index = relative_path.file_stem().and_then(|stem| stem.to_str()) == Some(self.cfg.index_name());
+        if self.cfg.pretty_urls.unwrap_or(false) && !is_index {
+            return Ok(output_base.join(relative_path.with_extension("")).join(self.cfg.index_file_name()));
+        }
+        Ok(output_base.join(relative_path))
+    }
+
+    // Context a page is rendered in: its front matter plus page.url, page.source_path, page.output_path
+    // and page.is_homepage
+    fn page_render_context(&self, path: &str, output_path: &Path, front_matter: &FrontMatter) -> TemplateContextPtr {
+        let ctx = TemplateContext::new(None);
+        ctx.borrow_mut().add_front_matter(front_matter);
+        ctx.borrow_mut().strings.insert("page.url".to_string(), self.page_url(output_path));
+        ctx.borrow_mut().strings.insert("page.source_path".to_string(), path.to_string());
+        ctx.borrow_mut().strings.insert("page.output_path".to_string(), self.site_relative_path(output_path));
+        if self.homepage.as_deref() == Some(path) {
+            ctx.borrow_mut().strings.insert("page.is_homepage".to_string(), "true".to_string());
+        }
+        ctx
+    }
+
+    // Entries of a list_md directory in file name order
+    fn list_md_entries(&self, dir: &str) -> Vec<PathBuf> {
+        let dir = self.cfg.relative_to_config_path(&PathBuf::from(dir));
+        let mut entries = fs::read_dir(dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        entries.sort();
+        entries
+    }
+
+    // Page size from a paginate value: a number, or true for the configured paginate
+    fn pagination_size(&self, value: &str) -> Option<usize> {
+        match value.trim().parse::<usize>() {
+            Ok(size) => Some(size).filter(|size| *size > 0),
+            Err(_) if is_truthy(value) => self.cfg.paginate.filter(|size| *size > 0),
+            Err(_) => None,
+        }
+    }
+
+    // Renderings of a listing page with paginate front matter: the page itself, then page/2/index.html
+    // and so on next to it, enough for every entry of its first list_md. Each comes with its page
+    // number and the total; pages without paginate come back alone
+    fn paginated_pages(&self, page: &Rc<TemplateNode>) -> Vec<(Rc<TemplateNode>, Option<PageNumber>)> {
+        let TemplateNode::Page { path, front_matter, content_node, output_path, parent } = &**page else {
+            return vec![(page.clone(), None)];
+        };
+        let Some(page_size) = front_matter.get("paginate").and_then(|size| self.pagination_size(&size.to_string())) else {
+            return vec![(page.clone(), None)];
+        };
+
+        let list_md_args = page.function_args("list_md").unwrap_or_default();
+        let total_items = match list_md_args.first() {
+            Some(dir) => self.list_md_entries(dir).len().min(list_md_limit(list_md_args).unwrap_or(usize::MAX)),
+            None => {
+                eprintln!("Warning: {} sets paginate but has no list_md to paginate", path);
+                0
+            }
+        };
+        let total_pages = total_items.div_ceil(page_size).max(1);
+        let first_page = self.site_relative_path(output_path);
+
+        (1..=total_pages).map(|page_number| {
+            let node = if page_number == 1 {
+                page.clone()
+            } else {
+                let page_path = pagination_page_path(&first_page, page_number, &self.cfg.index_file_name());
+                Rc::new(TemplateNode::Page {
+                    path: path.clone(),
+                    front_matter: front_matter.clone(),
+                    content_node: content_node.clone(),
+                    output_path: self.cfg.full_output_path().join(page_path),
+                    parent: parent.clone(),
+                })
+            };
+            (node, Some((page_number, total_pages)))
+        }).collect()
+    }
+
+    // page_number, total_pages, prev_url and next_url for one page of a paginated listing, with
+    // pagination_base pointing {{ pagination }} at its first page
+    fn add_pagination_context(&self, ctx: &TemplateContextPtr, first_page: &str, page_number: usize, total_pages: usize) {
+        let url = |page: usize| self.relative_url(&self.public_path(&pagination_page_path(first_page, page, &self.cfg.index_file_name())));
+        let mut ctx = ctx.borrow_mut();
+        ctx.strings.insert("page_number".to_string(), page_number.to_string());
+        ctx.strings.insert("total_pages".to_string(), total_pages.to_string());
+        ctx.strings.insert("pagination_base".to_string(), first_page.to_string());
+        ctx.strings.insert("prev_url".to_string(), if page_number > 1 { url(page_number - 1) } else { String::new() });
+        ctx.strings.insert("next_url".to_string(), if page_number < total_pages { url(page_number + 1) } else { String::new() });
+    }
+
+    // Extra renderings of a page from its outputs front matter, e.g. outputs: [html, "txt:plain"],
+    // each "format" or "format:layout". The HTML page is always the primary output; other formats are
+    // written next to it with the format as extension, using the named layout or one named after the format.
+    fn page_outputs(&mut self, page: &TemplateNode) -> Vec<Rc<TemplateNode>> {
+        let TemplateNode::Page { path, front_matter, content_node, output_path, .. } = page else {
+            return vec![];
+        };
+        let Some(outputs) = front_matter.get("outputs") else {
+            return vec![];
+        };
+
+        let mut pages = vec![];
+        for output in outputs.items() {
+            let (format, layout) = output.split_once(':')
+                .map(|(format, layout)| (format.trim(), layout.trim()))
+                .unwrap_or((output, output));
+            if format == "html" {
+                continue;
+            }
+            if format.is_empty() || !format.chars().all(|c| c.is_ascii_alphanumeric()) {
+                eprintln!("Warning: invalid output format {:?} in {}, expected e.g. txt or txt:plain", output, path);
+                continue;
+            }
+            let parent = match self.get_layout(layout) {
+                Ok(parent) => parent,
+                Err(e) => {
+                    eprintln!("Warning: skipping {} output of {}: {}", format, path, e);
+                    continue;
+                }
+            };
+            pages.push(TemplateNode::new_page(
+                path.clone(),
+                front_matter.clone(),
+                content_node.clone(),
+                output_path.with_extension(format),
+                Some(parent),
+            ));
+        }
+        pages
+    }
+
+    // A site string as the page being built sees it, with its directories' _site.yaml overrides applied
+    fn site_string(&self, key: &str) -> Option<String> {
+        self.page_site_strings.get(key).or_else(|| self.site_strings.get(key)).cloned()
+    }
+
+    // Site string overrides for a page from the _site.yaml files in its directory and each parent
+    // up to the content root, with deeper directories winning
+    fn directory_site_strings(&mut self, path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let root = self.cfg.full_content_path();
+        let page_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let Ok(relative_dir) = page_dir.strip_prefix(&root) else {
+            return Ok(HashMap::new());
+        };
+
+        let mut dirs = vec![root.clone()];
+        for component in relative_dir.components() {
+            dirs.push(dirs.last().unwrap().join(component));
+        }
+
+        let mut strings = HashMap::new();
+        for dir in dirs {
+            self.record_dependency(&dir.join("_site.yaml"));
+            if !self.directory_strings.contains_key(&dir) {
+                let overrides = self.load_directory_site_strings(&dir)?;
+                self.directory_strings.insert(dir.clone(), overrides);
+            }
+            strings.extend(self.directory_strings[&dir].clone());
+        }
+        Ok(strings)
+    }
+
+    fn load_directory_site_strings(&self, dir: &Path) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let path = dir.join("_site.yaml");
+        let mut strings = HashMap::new();
+        if !path.exists() {
+            return Ok(strings);
+        }
+        match load_yaml_data(path.to_str().unwrap())? {
+            Value::Mapping(mapping) => self.load_site_data_entries("", &mapping, &mut strings)?,
+            Value::Null => {}
+            other => return Err(format!("{} must be a mapping, found {}", path.display(), yaml_type_name(&other)).into()),
+        }
+        Ok(strings)
+    }
+
+    fn build_page(
+        &mut self,
+        path: &str,
+    ) -> Result<Rc<TemplateNode>, Box<dyn Error>> {
+        let (mut front_matter, markdown) = self.load_page_source(path)?;
+        let markdown = protect_raw_markdown(&markdown);
+
+        self.load_front_matter_json_data(&mut front_matter);
+
+        // Directory overrides sit between the page's own front matter and the global site strings
+        self.page_site_strings = self.directory_site_strings(path)?;
+
+        let heading_shift = front_matter.get("heading_shift").and_then(FrontMatterValue::as_str)
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .or(self.cfg.heading_shift)
+            .unwrap_or(0);
+        let allow_raw_html = self.cfg.allow_raw_html.unwrap_or(true);
+        let excerpt = page_excerpt(&markdown, self.cfg.excerpt_words.unwrap_or(50));
+        front_matter.insert("page.excerpt".to_string(), excerpt.into());
+        
+        // Convert markdown to HTML
+        let mut html_content = String::new();
+        let mut links = vec![];
+        let mut events = pulldown_cmark::Parser::new_ext(&markdown, Options::all())
+            .map(|event| match event {
+                // Rewrite links
+                Event::Start(Tag::Link { dest_url, link_type, title, id }) => {
+                    // println!("found link {}", dest_url);
+                    links.push(dest_url.to_string());
+                    let new_dest = self.relative_url(dest_url.as_ref());
+                    Event::Start(Tag::Link { link_type, dest_url: new_dest.into(), title, id })
+                }
+                // Rewrite images
+                Event::Start(Tag::Image { dest_url, link_type, title, id }) => {
+                    // println!("found img {}", dest_url);
+                    let new_dest = self.relative_url(dest_url.as_ref());
+                    Event::Start(Tag::Image { link_type, dest_url: new_dest.into(), title, id })
+                }
+                // Shift heading levels (h1 -> h2, ...) when embedding under a section
+                Event::Start(Tag::Heading { level, id, classes, attrs }) if heading_shift > 0 => {
+                    Event::Start(Tag::Heading { level: shift_heading_level(level, heading_shift), id, classes, attrs })
+                }
+                Event::End(TagEnd::Heading(level)) if heading_shift > 0 => {
+                    Event::End(TagEnd::Heading(shift_heading_level(level, heading_shift)))
+                }
+                // Show raw HTML as text instead of passing it through, for untrusted markdown
+                // (the excerpt marker is only a comment, so it can stay)
+                Event::Html(raw) | Event::InlineHtml(raw) if !allow_raw_html && !EXCERPT_MARKERS.contains(&raw.trim()) => Event::Text(raw),
+                // Pass through other events unchanged
+                _ => event,
+            })
+            .collect::<Vec<_>>();
+        // Reading progress needs heading IDs to track the current section
+        let reading_progress = self.cfg.reading_progress.unwrap_or(false);
+        let headings = assign_heading_ids(&mut events, self.cfg.heading_anchors.unwrap_or(false) || reading_progress);
+        let anchors = headings.iter().filter_map(|heading| heading.id.clone()).collect();
+        if reading_progress {
+            html_content.push_str("<div class=\"reading-progress-content\" data-reading-progress>\n");
+        }
+        html::push_html(&mut html_content, events.into_iter());
+        if reading_progress {
+            html_content.push_str("</div>\n");
+        }
+        
+        // Parse control blocks in the content
+        let content_node = self.parse_control_blocks(&html_content);
+        
+        // Create output path
+        let output_path = self.page_output_path(path, &front_matter)?;
+        self.page_links.insert(path.to_string(), links);
+        self.page_headings.insert(path.to_string(), headings);
+        self.page_anchors.insert(output_path.clone(), anchors);
+
+        // println!("output_path: {:?}", output_path);
+        
+        // Get the layout hierarchy
+        let layout = if let Some(layout_name) = front_matter.get("layout").and_then(FrontMatterValue::as_str) {
+            if layout_name.is_empty() {
+                None
+            } else {
+                let layout = self.get_layout(layout_name)
+                    .map_err(|e| format!("page {} uses layout {}, but {}", path, layout_name, e))?;
+                Some(layout)
+            }
+        } else {
+            None
+        };
+        
+        // Create the page with the layout as parent
+        Ok(TemplateNode::new_page(
+            path.to_string(),
+            front_matter,
+            content_node,
+            output_path,
+            layout,
+        ))
+    }
+
+    // Loads the YAML or JSON file named by json_data front matter (relative to the config, with
+    // variant files merged in) under the json_data_key front matter key, items by default
+    fn load_front_matter_json_data(&self, front_matter: &mut FrontMatter) {
+        let Some(json_path) = front_matter.get("json_data").and_then(FrontMatterValue::as_str) else {
+            return;
+        };
+        let full_path = self.cfg.relative_to_config_path(&PathBuf::from(json_path));
+        match self.load_yaml_data_merge_env_variant(&full_path.to_string_lossy()) {
+            Ok(json_data) => {
+                let key = front_matter.get("json_data_key")
+                    .and_then(FrontMatterValue::as_str)
+                    .unwrap_or("items")
+                    .to_string();
+                front_matter.insert(key, FrontMatterValue::Data(json_data));
+            }
+            Err(e) => eprintln!("Warning: could not load json_data {}: {}", json_path, e),
+        }
+    }
+
+    fn relative_url(&self, path: &str) -> String {
+        if has_protocol(path) || path.starts_with('#') {
+            return path.to_string();
+        }
+        
+        // Without site.url (warned about when loading site data) links stay site-relative
+        let mut base = self.site_strings.get("site.url").cloned().unwrap_or_default();
+        if let Some(canonical_host) = &self.cfg.canonical_host {
+            base = with_canonical_host(&base, canonical_host);
+        }
+        base = base.trim_end_matches('/').to_string();
+        let path = path.trim_start_matches('/').to_string();
+        format!("{}/{}", base, path)
+    }
+
+    // URL of a file under assets/, through its fingerprinted name when fingerprint_assets is on
+    fn asset_url(&self, path: &str) -> String {
+        if !self.cfg.fingerprint_assets.unwrap_or(false) {
+            return self.relative_url(&format!("assets/{}", path));
+        }
+        // Pages link to the fingerprint of the asset's contents, so they depend on it
+        match self.asset_sources.get(path) {
+            Some(sources) => sources.iter().for_each(|source| self.record_dependency(source)),
+            None => self.record_dependency(&self.cfg.relative_to_config_path(&PathBuf::from("assets").join(path))),
+        }
+        match self.asset_manifest.get(path) {
+            Some(fingerprinted) => self.relative_url(&format!("assets/{}", fingerprinted)),
+            None => {
+                eprintln!("Warning: asset {} not found in assets/", path);
+                self.relative_url(&format!("assets/{}", path))
+            }
+        }
+    }
+
+    // URL of a file listed in front matter: full URLs as they are, files under assets/ like
+    // {{ asset }} and other site paths like {{ relative-url }}
+    fn linked_file_url(&self, path: &str) -> String {
+        if has_protocol(path) || path.starts_with("//") {
+            return path.to_string();
+        }
+        match path.trim_start_matches('/').strip_prefix("assets/") {
+            Some(asset) => self.asset_url(asset),
+            None => self.relative_url(path),
+        }
+    }
+
+    // Public URL of a page from its output file path
+    fn page_url(&self, output_path: &Path) -> String {
+        self.relative_url(&self.public_path(&self.site_relative_path(output_path)))
+    }
+
+    // Language settings for the variant being built, if variant_languages has an entry for it
+    fn variant_language(&self) -> Option<&VariantLanguage> {
+        let variant = self.cfg.variant.as_ref()?;
+        self.cfg.variant_languages.as_ref()?.get(variant)
+    }
+
+    // Absolute URL of a page from its path relative to the output root, following clean_urls,
+    // trailing_slash and canonical_host; None when site.url isn't set
+    fn permalink(&self, site_relative_path: &str) -> Option<String> {
+        self.site_strings.get("site.url")
+            .filter(|url| !url.trim().is_empty())
+            .map(|_| self.relative_url(&self.public_path(site_relative_path)))
+    }
+
+    // Output file relative to the output root, with forward slashes, e.g. "docs/intro.html"
+    fn site_relative_path(&self, output_path: &Path) -> String {
+        let output_base = self.cfg.full_output_path();
+        let relative_path = output_path.strip_prefix(&output_base).unwrap_or(output_path);
+        relative_path.to_string_lossy().replace('\\', "/")
+    }
+
+    // Rewrites href and src attributes that point under site.url, or at the site root like
+    // /assets/style.css, into file paths relative to the page at output_path (relative to the output
+    // root), so the output can be browsed off disk. Links to other hosts are left alone
+    fn relativize_site_links(&self, html: &str, output_path: &str) -> String {
+        let bases = match self.site_strings.get("site.url").filter(|url| !url.trim().is_empty()) {
+            Some(site_url) => vec![
+                site_url.trim_end_matches('/').to_string(),
+                self.relative_url("").trim_end_matches('/').to_string(),
+            ],
+            None => vec![],
+        };
+        // Root-relative links include the path of a site.url like https://example.com/blog/
+        let site_path = bases.last()
+            .and_then(|base| base.split_once("://"))
+            .and_then(|(_, rest)| rest.find('/').map(|pos| rest[pos..].to_string()))
+            .unwrap_or_default();
+
+        let mut html = html.to_string();
+        for pattern in ["href=\"", "src=\"", "href='", "src='"] {
+            let quote = &pattern[pattern.len() - 1..];
+            let mut output = String::with_capacity(html.len());
+            let mut remaining = html.as_str();
+            while let Some(pos) = remaining.find(pattern) {
+                let value_start = pos + pattern.len();
+                output.push_str(&remaining[..value_start]);
+                remaining = &remaining[value_start..];
+                let value_end = remaining.find(quote).unwrap_or(remaining.len());
+                let value = &remaining[..value_end];
+                let site_link = |base: &str| value.strip_prefix(base).filter(|path| path.is_empty() || path.starts_with(['/', '?', '#']));
+                let root_relative = value.starts_with('/') && !value.starts_with("//");
+                match bases.iter().find_map(|base| site_link(base)) {
+                    Some(path) => output.push_str(&self.offline_link(output_path, path)),
+                    None if root_relative => output.push_str(&self.offline_link(output_path, site_link(&site_path).unwrap_or(value))),
+                    None => output.push_str(value),
+                }
+                remaining = &remaining[value_end..];
+            }
+            output.push_str(remaining);
+            html = output;
+        }
+        html
+    }
+
+    // Relative link from the page file at from_output_path to the file serving a site path
+    fn offline_link(&self, from_output_path: &str, target: &str) -> String {
+        let (target, suffix) = match target.find(['#', '?']) {
+            Some(pos) => target.split_at(pos),
+            None => (target, ""),
+        };
+        let from_dir = from_output_path.trim_start_matches('/').rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut link = link_between(from_dir, &self.output_file_for(target));
+        if link.is_empty() {
+            link.push_str("./");
+        }
+        link + suffix
+    }
+
+    // The output file a site path is served from, as clean_urls, trailing_slash and pretty_urls
+    // wrote it: "about/" may be about/index.html or about.html, and "about" may be about.html
+    fn output_file_for(&self, site_path: &str) -> String {
+        let site_path = site_path.trim_start_matches('/');
+        let index_file = self.cfg.index_file_name();
+        let file_name = site_path.rsplit('/').next().unwrap_or_default();
+        let candidates = if file_name.is_empty() {
+            let mut candidates = vec![format!("{}{}", site_path, index_file)];
+            if let Some(page) = site_path.strip_suffix('/').filter(|page| !page.is_empty()) {
+                candidates.push(format!("{}.html", page));
+            }
+            candidates
+        } else if !file_name.contains('.') {
+            vec![format!("{}.html", site_path), format!("{}/{}", site_path, index_file), site_path.to_string()]
+        } else {
+            vec![site_path.to_string()]
+        };
+
+        let output_base = self.cfg.full_output_path();
+        candidates.iter()
+            .find(|candidate| {
+                self.pages.iter().any(|page| self.site_relative_path(&page.output_path) == **candidate)
+                    || output_base.join(candidate).is_file()
+            })
+            .unwrap_or(&candidates[0])
+            .clone()
+    }
+
+    // Link from the page at output_path (relative to the output root) to a site path, using
+    // ../ steps instead of the site URL so it works wherever the output is served from
+    fn relative_link(&self, from_output_path: &str, target: &str) -> String {
+        if has_protocol(target) || target.starts_with('#') {
+            return target.to_string();
+        }
+        let (target, suffix) = match target.find(['#', '?']) {
+            Some(pos) => target.split_at(pos),
+            None => (target, ""),
+        };
+
+        // Browsers resolve links against the directory of the URL the page is served at
+        let from_url = self.public_path(from_output_path);
+        let from_dir = match from_url.rfind('/') {
+            Some(pos) => &from_url[..pos],
+            None => "",
+        };
+
+        let mut link = link_between(from_dir, &self.public_path(target.trim_start_matches('/')));
+        if link.is_empty() {
+            link.push_str("./");
+        }
+        link + suffix
+    }
+
+    // Path a page is served at: with clean_urls "about.html" becomes "about" (or "about/" with
+    // trailing_slash) and "docs/index.html" (or the configured index_filename) becomes "docs/".
+    // pretty_urls alone only turns index files into their directory
+    fn public_path(&self, relative_path: &str) -> String {
+        if !self.cfg.clean_urls.unwrap_or(false) {
+            if !self.cfg.pretty_urls.unwrap_or(false) {
+                return relative_path.to_string();
+            }
+            let index_file = self.cfg.index_file_name();
+            return match relative_path.trim_start_matches('/').strip_suffix(index_file.as_str()) {
+                Some(dir) if dir.is_empty() || dir.ends_with('/') => dir.to_string(),
+                _ => relative_path.to_string(),
+            };
+        }
+
+        let path = relative_path.trim_start_matches('/');
+        let path = path.strip_suffix(".html").unwrap_or(path);
+        let index_name = self.cfg.index_name();
+        if path == index_name {
+            String::new()
+        } else if let Some(dir) = path.strip_suffix(index_name).and_then(|p| p.strip_suffix('/')) {
+            format!("{}/", dir)
+        } else if self.cfg.trailing_slash.unwrap_or(false) {
+            format!("{}/", path)
+        } else {
+            path.to_string()
+        }
+    }
+
+    // Loads data/robots_config.yaml merged with robots_config.<variant>.yaml, resolving a
+    // site-relative sitemap against the variant's site.url
+    fn load_robots_config(&self) -> Result<Option<RobotsConfig>, Box<dyn std::error::Error>> {
+        let config_path = self.cfg.relative_to_config_path(&PathBuf::from("data/robots_config.yaml"));
+        if fs::exists(&config_path)? {
+            let config_path = config_path.to_str().unwrap();
+            let value = self.load_yaml_data_merge_env_variant(config_path)?;
+            let mut config: RobotsConfig = serde_yaml::from_value(value)
+                .map_err(|e| format!("invalid {}: {}", config_path, e))?;
+            config.sitemap = config.sitemap.map(|sitemap| self.relative_url(&sitemap));
+            Ok(Some(config))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Loads from -> to mappings from data/redirects.yaml, if present
+    fn load_redirects(&self) -> Result<Option<Redirects>, Box<dyn Error>> {
+        let path = self.cfg.relative_to_config_path(&PathBuf::from("data/redirects.yaml"));
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let path = path.to_str().unwrap();
+        match self.load_yaml_data_merge_env_variant(path)? {
+            Value::Mapping(mapping) => mapping.iter()
+                .map(|(k, v)| match (k.as_str(), v.as_str()) {
+                    (Some(from), Some(to)) => Ok((from.to_string(), to.to_string())),
+                    _ => Err(format!("redirects in {} must map strings to strings, found {:?}: {:?}", path, k, v).into()),
+                })
+                .collect::<Result<Redirects, Box<dyn Error>>>()
+                .map(Some),
+            Value::Null => Ok(Some(vec![])),
+            _ => Err(format!("{} must be a mapping of from -> to paths", path).into()),
+        }
+    }
+}
+
+impl SitemapXmlNode {
+    // Creates a new sitemap entry with required URL
+    pub fn new(loc: String) -> Self {
+        Self {
+            loc,
+            lastmod: None,
+            changefreq: None,
+            priority: None,
+            alternates: Vec::new(),
+        }
+    }
+    
+    // Sets the last modification date
+    pub fn with_lastmod(mut self, lastmod: DateTime<Utc>) -> Self {
+        self.lastmod = Some(lastmod);
+        self
+    }
+    
+    // Sets the change frequency
+    pub fn with_changefreq(mut self, changefreq: ChangeFrequency) -> Self {
+        self.changefreq = Some(changefreq);
+        self
     }
     
     // Sets the priority (clamped between 0.0 and 1.0)
@@ -942,35 +3183,33 @@ impl SitemapXmlNode {
         self
     }
     
-    // Generates the XML for this sitemap entry
-    pub fn to_xml(&self) -> String {
+    // Generates the XML for this sitemap entry, one element per line when pretty
+    pub fn to_xml(&self, pretty: bool, depth: usize) -> String {
         let mut xml = String::new();
         
-        xml.push_str("<url>\n");
-        xml.push_str(&format!("  <loc>{}</loc>\n", self.loc));
+        push_xml_line(&mut xml, pretty, depth, "<url>");
+        push_xml_line(&mut xml, pretty, depth + 1, &format!("<loc>{}</loc>", escape_xml(&self.loc)));
         
         if let Some(lastmod) = self.lastmod {
-            xml.push_str(&format!("  <lastmod>{}</lastmod>\n", lastmod.to_rfc3339()));
+            push_xml_line(&mut xml, pretty, depth + 1, &format!("<lastmod>{}</lastmod>", lastmod.to_rfc3339()));
         }
         
         if let Some(changefreq) = &self.changefreq {
-            xml.push_str(&format!("  <changefreq>{}</changefreq>\n", changefreq));
+            push_xml_line(&mut xml, pretty, depth + 1, &format!("<changefreq>{}</changefreq>", changefreq));
         }
         
         if let Some(priority) = self.priority {
-            xml.push_str(&format!("  <priority>{:.1}</priority>\n", priority));
+            push_xml_line(&mut xml, pretty, depth + 1, &format!("<priority>{:.1}</priority>", priority));
         }
         
-        if !self.alternates.is_empty() {
-            for alt in &self.alternates {
-                xml.push_str(&format!(
-                    "  <xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>\n",
-                    alt.lang, alt.url
-                ));
-            }
+        for alt in &self.alternates {
+            push_xml_line(&mut xml, pretty, depth + 1, &format!(
+                "<xhtml:link rel=\"alternate\" hreflang=\"{}\" href=\"{}\"/>",
+                escape_xml(&alt.lang), escape_xml(&alt.url)
+            ));
         }
         
-        xml.push_str("</url>");
+        push_xml_line(&mut xml, pretty, depth, "</url>");
         xml
     }
     
@@ -987,16 +3226,16 @@ impl SitemapXmlNode {
             .with_lastmod(lastmod.unwrap_or_else(Utc::now)))
     }
 
-    pub fn generate_sitemap_xml(nodes: &[SitemapXmlNode]) -> String {
+    pub fn generate_sitemap_xml(nodes: &[SitemapXmlNode], pretty: bool) -> String {
         let mut s = String::new();
-        s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
-        s.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">");
+        push_xml_line(&mut s, pretty, 0, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        push_xml_line(&mut s, pretty, 0, "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\" xmlns:xhtml=\"http://www.w3.org/1999/xhtml\">");
 
         for node in nodes {
-            s.push_str(&node.to_xml());
+            s.push_str(&node.to_xml(pretty, 1));
         }
 
-        s.push_str("</urlset>");
+        push_xml_line(&mut s, pretty, 0, "</urlset>");
         s
     }
 }
@@ -1015,18 +3254,677 @@ fn parse_yaml_front_matter(front_matter: &str) -> Result<FrontMatter, Box<dyn Er
         // println!("front_matter is empty");
         Ok(FrontMatter::new())
     } else {
-        serde_yaml::from_str(front_matter)
-            .map_err(|e| e.into())
+        let values: serde_yaml::Mapping = serde_yaml::from_str(front_matter)?;
+        front_matter_values("", &values)
     }
 }
-fn get_md_files_recursive(path: &Path) -> Vec<String> {
-    // List of directories to ignore
-    const IGNORED_DIRS: &[&str] = &["assets", "templates", "data"];
-    
-    fs::read_dir(path).ok()
-        .map(|entries| {
-            entries.filter_map(|entry| entry.ok())
-                .flat_map(|entry| {
+
+// Converts a front matter mapping, nested mappings included; lists may only hold plain values
+fn front_matter_values(prefix: &str, mapping: &serde_yaml::Mapping) -> Result<FrontMatter, Box<dyn Error>> {
+    mapping.iter()
+        .map(|(key, value)| {
+            let key = front_matter_scalar(key)
+                .ok_or_else(|| format!("front matter keys must be plain values, found {}", yaml_type_name(key)))?;
+            let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+            let value = match value {
+                Value::Sequence(items) => items.iter()
+                    .map(front_matter_scalar)
+                    .collect::<Option<Vec<_>>>()
+                    .map(FrontMatterValue::List)
+                    .ok_or_else(|| format!("front matter {} must be a list of strings, numbers or booleans", path))?,
+                Value::Mapping(nested) => FrontMatterValue::Map(front_matter_values(&path, nested)?),
+                other => front_matter_scalar(other)
+                    .map(FrontMatterValue::Scalar)
+                    .ok_or_else(|| format!("front matter {} has unsupported type {}", path, yaml_type_name(other)))?,
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+// Front matter as template strings: lists comma-separated and nested mappings as dotted keys,
+// so author: { name: x } is {{ author.name }}
+fn front_matter_strings(front_matter: &FrontMatter) -> HashMap<String, String> {
+    let mut strings = HashMap::new();
+    for (key, value) in front_matter {
+        match value {
+            FrontMatterValue::Map(nested) => strings.extend(front_matter_strings(nested)
+                .into_iter()
+                .map(|(nested_key, value)| (format!("{}.{}", key, nested_key), value))),
+            FrontMatterValue::Data(_) => {}
+            other => {
+                strings.insert(key.clone(), other.to_string());
+            }
+        }
+    }
+    strings
+}
+
+fn yaml_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Sequence(_) => "sequence",
+        Value::Mapping(_) => "mapping",
+        Value::Tagged(_) => "tagged value",
+    }
+}
+
+fn front_matter_scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => Some(String::new()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// Splits a comma-separated front matter list, dropping empty entries
+fn front_matter_list(value: &str) -> Vec<&str> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).collect()
+}
+
+// Finds the first tag starting with one of targets that isn't inside a nested block, where nesting
+// is opened by tags starting with one of openers and closed by one of closers. Returns its start
+// and end offsets
+fn find_block_tag(content: &str, targets: &[&str], openers: &[&str], closers: &[&str]) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut offset = 0;
+    while let Some(open_pos) = content[offset..].find("{{") {
+        let start = offset + open_pos;
+        let close_pos = content[start..].find("}}")? + start;
+        let tag = content[start + 2..close_pos].trim();
+        offset = close_pos + 2;
+
+        let first_word = tag.split_whitespace().next().unwrap_or("");
+        if depth == 0 && targets.contains(&first_word) {
+            return Some((start, offset));
+        } else if openers.contains(&first_word) && first_word != tag {
+            depth += 1;
+        } else if closers.contains(&tag) && depth > 0 {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+// Parses front matter dates such as 2024-01-31, 2024-01-31 10:00:00 or RFC 3339
+fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .ok()
+        .or_else(|| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok().map(|d| d.and_utc()))
+        .or_else(|| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+            .map(|d| d.and_utc()))
+}
+
+// When the current page's source file was last modified, or empty if that can't be read
+fn page_modified_time(ctx: &TemplateContextPtr, global_context: &GlobalContext, format: &str) -> String {
+    let Some(path) = ctx.borrow().get_string("page.source_path") else {
+        return String::new();
+    };
+    fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .map(|modified| format_in_timezone(DateTime::<Utc>::from(modified), global_context.cfg.timezone.as_deref(), format))
+        .unwrap_or_default()
+}
+
+// A function argument that names a page or site key resolves to its value, anything else is literal text
+fn string_arg(arg: &str, ctx: &TemplateContextPtr, global_context: &GlobalContext) -> String {
+    ctx.borrow().get_string(arg)
+        .or_else(|| global_context.site_string(arg))
+        .unwrap_or_else(|| arg.to_string())
+}
+
+// Shared by truncate and truncatewords: the text from the first argument and the limit from the second
+fn truncate_function(name: &str, args: &[String], ctx: &TemplateContextPtr, global_context: &GlobalContext, truncate: fn(&str, usize) -> String) -> String {
+    let Some(text) = args.first().map(|arg| string_arg(arg, ctx, global_context)) else {
+        eprintln!("Warning: {} needs a key or text and a limit, e.g. {{{{ {} description 30 }}}}", name, name);
+        return String::new();
+    };
+    match args.get(1).and_then(|limit| limit.trim().parse::<usize>().ok()) {
+        Some(limit) => truncate(&text, limit),
+        None => {
+            eprintln!("Warning: {} needs a number as its limit, e.g. {{{{ {} description 30 }}}}", name, name);
+            text
+        }
+    }
+}
+
+// A strftime format argument, or the default when it's missing or invalid (chrono panics on those)
+fn date_format_arg<'a>(arg: Option<&'a String>, function: &str, default: &'a str) -> &'a str {
+    match arg {
+        Some(format) if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) => {
+            eprintln!("Warning: {} format {} is not a valid strftime format, using {}", function, format, default);
+            default
+        }
+        Some(format) => format,
+        None => default,
+    }
+}
+
+// Formats a date value keeping its own offset when it has one, or None when it can't be parsed
+fn format_date_value(value: &str, format: &str) -> Option<String> {
+    match DateTime::parse_from_rfc3339(value.trim()) {
+        Ok(date) => Some(date.format(format).to_string()),
+        Err(_) => parse_date(value).map(|date| date.format(format).to_string()),
+    }
+}
+
+// Formats a time in the configured timezone: local (the default), utc or a fixed offset like +02:00
+fn format_in_timezone(time: DateTime<Utc>, timezone: Option<&str>, format: &str) -> String {
+    match timezone.map(|tz| tz.trim().to_lowercase()).as_deref() {
+        None | Some("local") => time.with_timezone(&Local).format(format).to_string(),
+        Some("utc") => time.format(format).to_string(),
+        Some(offset) => match offset.parse::<FixedOffset>() {
+            Ok(offset) => time.with_timezone(&offset).format(format).to_string(),
+            Err(_) => {
+                eprintln!("Warning: invalid timezone {}, expected local, utc or an offset like +02:00", offset);
+                time.with_timezone(&Local).format(format).to_string()
+            }
+        },
+    }
+}
+
+// These take front matter or rendered context strings alike
+fn is_draft<V: ToString>(front_matter: &HashMap<String, V>) -> bool {
+    front_matter.get("draft").is_some_and(|v| matches!(v.to_string().trim(), "true" | "yes"))
+}
+
+fn is_future_dated<V: ToString>(front_matter: &HashMap<String, V>) -> bool {
+    front_matter.get("date")
+        .and_then(|d| parse_date(&d.to_string()))
+        .is_some_and(|d| d > Utc::now())
+}
+
+// Sorts pages by a "field" or "field:desc" spec; values that parse as dates compare as dates
+fn sort_pages(pages: &mut [&PageInfo], spec: &str) {
+    sort_by_front_matter(pages, spec, |page| Some(&page.front_matter));
+}
+
+// Sorts anything with front matter by a sort_pages spec; items without the field sort first
+fn sort_by_front_matter<T>(items: &mut [T], spec: &str, front_matter: impl Fn(&T) -> Option<&FrontMatter>) {
+    let (field, descending) = match spec.split_once(':') {
+        Some((field, direction)) => (field.trim(), direction.trim().eq_ignore_ascii_case("desc")),
+        None => (spec.trim(), false),
+    };
+
+    items.sort_by(|a, b| {
+        let (a, b) = (
+            front_matter(a).and_then(|fm| fm.get(field)).and_then(FrontMatterValue::as_str),
+            front_matter(b).and_then(|fm| fm.get(field)).and_then(FrontMatterValue::as_str),
+        );
+        let ordering = match (a.and_then(parse_date), b.and_then(parse_date)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a.cmp(&b),
+        };
+        if descending { ordering.reverse() } else { ordering }
+    });
+}
+
+// The optional entry limit of a list_md call, its fourth argument
+fn list_md_limit(args: &[String]) -> Option<usize> {
+    args.get(3).and_then(|limit| limit.trim().parse::<usize>().ok())
+}
+
+// Parses --since as a window back from now (30s, 10m, 2h, 1d, 1w) or as a date/timestamp
+fn parse_since(since: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let since = since.trim();
+    let window = since.char_indices().last()
+        .and_then(|(i, unit)| since[..i].parse::<i64>().ok().map(|n| (n, unit)))
+        .and_then(|(n, unit)| match unit {
+            's' => Some(chrono::Duration::seconds(n)),
+            'm' => Some(chrono::Duration::minutes(n)),
+            'h' => Some(chrono::Duration::hours(n)),
+            'd' => Some(chrono::Duration::days(n)),
+            'w' => Some(chrono::Duration::weeks(n)),
+            _ => None,
+        });
+
+    window.map(|window| Utc::now() - window)
+        .or_else(|| parse_date(since))
+        .ok_or_else(|| format!("could not parse --since {}, expected e.g. 10m, 2h, 1d or 2024-01-31", since).into())
+}
+
+// Drafts and pages dated in the future
+fn is_unpublished<V: ToString>(front_matter: &HashMap<String, V>) -> bool {
+    is_draft(front_matter) || is_future_dated(front_matter)
+}
+
+// Whether an HTML page declares its charset via <meta charset> or an http-equiv content type
+fn declares_charset(html: &str) -> bool {
+    let html = html.to_ascii_lowercase();
+    html.contains("<meta charset") || (html.contains("http-equiv") && html.contains("charset="))
+}
+
+// Inserts a snippet right before the closing tag (e.g. </head>), or appends it if the tag is missing
+fn insert_before_closing_tag(html: &str, tag: &str, snippet: &str) -> String {
+    let closing = format!("</{}>", tag);
+    match html.rfind(&closing) {
+        Some(pos) => format!("{}{}{}", &html[..pos], snippet, &html[pos..]),
+        None => format!("{}{}", html, snippet),
+    }
+}
+
+// Resolves symlinks and .. in the part of path that exists, keeping the rest as written
+fn canonicalize_existing_prefix(path: &Path) -> PathBuf {
+    let mut missing = vec![];
+    let mut existing = path;
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            return missing.iter().rev().fold(canonical, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+// Renders code through the markdown renderer as a fenced block, the same as code in pages
+fn render_code_block(code: &str, language: &str) -> String {
+    let longest_backtick_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_backtick_run.max(2) + 1);
+    let markdown = format!("{}{}\n{}\n{}\n", fence, language, code, fence);
+    let mut html_content = String::new();
+    html::push_html(&mut html_content, pulldown_cmark::Parser::new_ext(&markdown, Options::all()));
+    html_content
+}
+
+// Output path of page number `page` of a listing whose first page is first_page, e.g. page 2 of
+// blog/index.html is blog/page/2/index.html, with index_file naming the directory index
+fn pagination_page_path(first_page: &str, page: usize, index_file: &str) -> String {
+    if page <= 1 {
+        return first_page.to_string();
+    }
+    match first_page.rfind('/') {
+        Some(pos) => format!("{}/page/{}/{}", &first_page[..pos], page, index_file),
+        None => format!("page/{}/{}", page, index_file),
+    }
+}
+
+// Whether an if condition's value counts as true: present, and not empty, "0" or "false"
+fn is_truthy(value: &str) -> bool {
+    !matches!(value.trim(), "" | "0" | "false")
+}
+
+// The literal in a comparison like `status == "published"`, with its quotes removed; markdown's
+// smart quotes and &quot; entities count as plain quotes
+fn condition_literal(literal: &str) -> String {
+    let literal = literal.trim().replace("&quot;", "\"").replace(['\u{201C}', '\u{201D}'], "\"");
+    literal.strip_prefix('"').and_then(|l| l.strip_suffix('"'))
+        .or_else(|| literal.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')))
+        .unwrap_or(&literal)
+        .to_string()
+}
+
+// Percent-encodes everything but unreserved characters, for query string values
+fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Decodes %XX escapes in a URL path; invalid escapes are kept as they are
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Share link for a well-known network, by name
+fn default_share_url(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "twitter" | "x" => Some("https://twitter.com/intent/tweet?url={url}&text={title}"),
+        "mastodon" => Some("https://mastodonshare.com/?url={url}&text={title}"),
+        "linkedin" => Some("https://www.linkedin.com/sharing/share-offsite/?url={url}"),
+        "email" => Some("mailto:?subject={title}&body={url}"),
+        _ => None,
+    }
+}
+
+// Markdown rendered to HTML with the same options as pages
+fn render_markdown(markdown: &str) -> String {
+    let mut html = String::new();
+    html::push_html(&mut html, pulldown_cmark::Parser::new_ext(markdown, Options::all()));
+    html
+}
+
+const EXCERPT_MARKERS: &[&str] = &["<!--more-->", "<!-- more -->"];
+
+// The HTML excerpt of a page's markdown: everything before an excerpt marker, else its first
+// paragraph, cut to max_words words when longer
+fn page_excerpt(markdown: &str, max_words: usize) -> String {
+    if let Some(before) = EXCERPT_MARKERS.iter().find_map(|marker| markdown.split_once(marker).map(|(before, _)| before)) {
+        return render_markdown(before.trim_end());
+    }
+
+    let mut in_paragraph = false;
+    let mut events = vec![];
+    for event in pulldown_cmark::Parser::new_ext(markdown, Options::all()) {
+        match event {
+            Event::Start(Tag::Paragraph) => in_paragraph = true,
+            Event::End(TagEnd::Paragraph) if in_paragraph => break,
+            event if in_paragraph => events.push(event),
+            _ => {}
+        }
+    }
+    if events.is_empty() {
+        return String::new();
+    }
+    let mut html = String::new();
+    html::push_html(&mut html, events.into_iter());
+    let text = strip_html(&html);
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    if words.len() > max_words {
+        format!("<p>{}…</p>", escape_xml(&words[..max_words].join(" ")))
+    } else {
+        format!("<p>{}</p>", html)
+    }
+}
+
+// URL-safe slug shared by heading IDs, tag pages and {{ slugify }}: lowercase, accented Latin
+// letters as ASCII, and runs of anything else as single hyphens, e.g. "Héllo, World!" -> "hello-world".
+// Letters of other scripts are kept
+fn slugify(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.to_lowercase().chars() {
+        match transliterate(c) {
+            Some(replacement) => ascii.push_str(replacement),
+            // Combining accents, as in decomposed "e\u{301}", go with the letter before them
+            None if ('\u{300}'..='\u{36F}').contains(&c) => {}
+            None => ascii.push(c),
+        }
+    }
+    ascii
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// ASCII spelling of a lowercase accented Latin letter
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĳ' => "ij",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' | 'ŉ' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+// Collects a page's headings and their IDs; with generate, headings without an explicit {#id} get
+// one from their text, made unique within the page with -1, -2, ... suffixes
+fn assign_heading_ids(events: &mut [Event], generate: bool) -> Vec<PageHeading> {
+    let mut headings = vec![];
+    let mut ids: Vec<String> = vec![];
+    let mut heading_start = None;
+    let mut heading_text = String::new();
+    for i in 0..events.len() {
+        match &events[i] {
+            Event::Start(Tag::Heading { .. }) => {
+                heading_start = Some(i);
+                heading_text.clear();
+            }
+            Event::Text(text) | Event::Code(text) if heading_start.is_some() => heading_text.push_str(text),
+            Event::End(TagEnd::Heading(_)) => {
+                let Some(start) = heading_start.take() else {
+                    continue;
+                };
+                if let Event::Start(Tag::Heading { level, id, .. }) = &mut events[start] {
+                    match id {
+                        Some(id) => ids.push(id.to_string()),
+                        None if generate => {
+                            let base = match slugify(&heading_text) {
+                                slug if slug.is_empty() => "section".to_string(),
+                                slug => slug,
+                            };
+                            let mut slug = base.clone();
+                            let mut n = 1;
+                            while ids.contains(&slug) {
+                                slug = format!("{}-{}", base, n);
+                                n += 1;
+                            }
+                            *id = Some(slug.clone().into());
+                            ids.push(slug);
+                        }
+                        None => {}
+                    }
+                    headings.push(PageHeading {
+                        level: *level as usize,
+                        id: id.as_ref().map(ToString::to_string),
+                        text: heading_text.trim().to_string(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+// A nested list of links to the headings from min_level to max_level that have IDs
+fn render_toc(headings: &[PageHeading], min_level: usize, max_level: usize) -> String {
+    let mut html = String::new();
+    let mut open_levels: Vec<usize> = vec![];
+    for heading in headings.iter().filter(|h| (min_level..=max_level).contains(&h.level)) {
+        let Some(id) = &heading.id else {
+            continue;
+        };
+        match open_levels.last() {
+            Some(&level) if heading.level > level => {
+                html.push_str("<ul>");
+                open_levels.push(heading.level);
+            }
+            Some(_) => {
+                while open_levels.len() > 1 && open_levels.last().is_some_and(|&level| heading.level < level) {
+                    html.push_str("</li></ul>");
+                    open_levels.pop();
+                }
+                html.push_str("</li>");
+            }
+            None => {
+                html.push_str("<nav class=\"toc\"><ul>");
+                open_levels.push(heading.level);
+            }
+        }
+        html.push_str(&format!("<li><a href=\"#{}\">{}</a>", escape_xml(id), escape_xml(&heading.text)));
+    }
+    if open_levels.is_empty() {
+        return String::new();
+    }
+    html.push_str(&"</li></ul>".repeat(open_levels.len()));
+    html.push_str("</nav>");
+    html
+}
+
+// Replaces each {{ key }} in value with that front matter value, or None if a key is missing
+fn fill_front_matter_expressions(value: &str, front_matter: &FrontMatter) -> Option<String> {
+    let mut result = String::new();
+    let mut remaining = value;
+    while let Some(open_pos) = remaining.find("{{") {
+        let close_pos = remaining[open_pos..].find("}}")? + open_pos;
+        let key = remaining[open_pos + 2..close_pos].trim();
+        result.push_str(&remaining[..open_pos]);
+        result.push_str(front_matter.get(key)?.as_str()?.trim());
+        remaining = &remaining[close_pos + 2..];
+    }
+    result.push_str(remaining);
+    Some(result)
+}
+
+// Whether today, in the configured timezone, is within start..=end (YYYY-MM-DD dates).
+// Invalid dates warn and count as outside the range.
+fn build_date_between(start: &str, end: &str, timezone: Option<&str>) -> bool {
+    let parse = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| eprintln!("Warning: invalid date {} in if_date_between, expected YYYY-MM-DD", date))
+        .ok();
+    let (Some(start), Some(end)) = (parse(start), parse(end)) else {
+        return false;
+    };
+    if start > end {
+        eprintln!("Warning: if_date_between start {} is after its end {}", start, end);
+    }
+    let today = format_in_timezone(Utc::now(), timezone, "%Y-%m-%d");
+    NaiveDate::parse_from_str(&today, "%Y-%m-%d").is_ok_and(|today| start <= today && today <= end)
+}
+
+// An environment variable counts as set unless it is missing, empty, "0" or "false"
+fn env_flag_is_set(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| !matches!(value.trim().to_lowercase().as_str(), "" | "0" | "false"))
+}
+
+// Braces of raw text ({{ raw }} blocks and \{{ escapes) are swapped for private use characters
+// while rendering, so no substitution matches them, and put back by restore_raw_text at the end
+const RAW_OPEN_BRACE: char = '\u{E000}';
+const RAW_CLOSE_BRACE: char = '\u{E001}';
+
+fn protect_raw_text(text: &str) -> String {
+    text.replace('{', &RAW_OPEN_BRACE.to_string()).replace('}', &RAW_CLOSE_BRACE.to_string())
+}
+
+fn restore_raw_text(text: String) -> String {
+    if !text.contains([RAW_OPEN_BRACE, RAW_CLOSE_BRACE]) {
+        return text;
+    }
+    text.replace(RAW_OPEN_BRACE, "{").replace(RAW_CLOSE_BRACE, "}")
+}
+
+// Protects {{ raw }} blocks and \{{ \}} escapes in a markdown page before the markdown pass, which
+// would otherwise take the backslash as its own escape and split raw blocks into paragraphs
+fn protect_raw_markdown(markdown: &str) -> String {
+    let mut output = String::with_capacity(markdown.len());
+    let mut remaining = markdown;
+    while let Some(open_pos) = remaining.find("{{") {
+        let before = &remaining[..open_pos];
+        if let Some(before) = before.strip_suffix('\\') {
+            output.push_str(&unescape_close_delimiters(before));
+            output.push_str(&protect_raw_text("{{"));
+            remaining = &remaining[open_pos + 2..];
+            continue;
+        }
+        output.push_str(&unescape_close_delimiters(before));
+        remaining = &remaining[open_pos..];
+
+        let Some(close_pos) = remaining.find("}}") else {
+            break;
+        };
+        if remaining[2..close_pos].trim() == "raw" {
+            let (inner_content, new_remaining) = GlobalContext::parse_block_content(&remaining[close_pos + 2..], "endraw");
+            output.push_str(&protect_raw_text(inner_content));
+            remaining = new_remaining;
+        } else {
+            output.push_str(&remaining[..close_pos + 2]);
+            remaining = &remaining[close_pos + 2..];
+        }
+    }
+    output.push_str(&unescape_close_delimiters(remaining));
+    output
+}
+
+// \}} in template text is a literal }}
+fn unescape_close_delimiters(text: &str) -> String {
+    text.replace("\\}}", &protect_raw_text("}}"))
+}
+
+// Path from the directory from_dir to target, both relative to the site root, in ../ steps
+fn link_between(from_dir: &str, target: &str) -> String {
+    let from_segments = from_dir.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    let target_segments = target.split('/').collect::<Vec<_>>();
+    let (target_dirs, target_file) = target_segments.split_at(target_segments.len() - 1);
+    let target_dirs = target_dirs.iter().filter(|s| !s.is_empty()).copied().collect::<Vec<_>>();
+
+    let common = from_segments.iter().zip(&target_dirs).take_while(|(a, b)| a == b).count();
+    let mut link = "../".repeat(from_segments.len() - common);
+    for dir in &target_dirs[common..] {
+        link.push_str(dir);
+        link.push('/');
+    }
+    link.push_str(target_file[0]);
+    link
+}
+
+// Finds {{ ... }} placeholders left in rendered output, skipping control tags and \{{ escapes
+fn find_unresolved_placeholders(html: &str) -> Vec<String> {
+    const CONTROL_TAGS: &[&str] = &["if", "else", "elseif", "endif", "if_env", "endif_env", "if_date_between", "foreach", "endforeach"];
+
+    let mut found = vec![];
+    let mut remaining = html;
+    while let Some(open_pos) = remaining.find("{{") {
+        let escaped = remaining[..open_pos].ends_with('\\');
+        let Some(close_pos) = remaining[open_pos..].find("}}").map(|p| p + open_pos) else {
+            break;
+        };
+
+        let placeholder = &remaining[open_pos..close_pos + 2];
+        let first_word = placeholder[2..placeholder.len() - 2].split_whitespace().next().unwrap_or("");
+        if !escaped && !CONTROL_TAGS.contains(&first_word) {
+            found.push(placeholder.to_string());
+        }
+        remaining = &remaining[close_pos + 2..];
+    }
+    found
+}
+
+// With skip_project_dirs, leaves out the assets, templates and data directories that share the
+// project root when content isn't kept in its own content_dir. The output directory (canonical, as
+// output_dir) is always left out, so generated files are never read back in as pages
+fn get_md_files_recursive(path: &Path, skip_project_dirs: bool, extensions: &[String], output_dir: &Path) -> Vec<String> {
+    // List of directories to ignore
+    const IGNORED_DIRS: &[&str] = &["assets", "templates", "data"];
+    
+    fs::read_dir(path).ok()
+        .map(|entries| {
+            entries.filter_map(|entry| entry.ok())
+                .flat_map(|entry| {
                     let path = entry.path();
                     
                     // Skip if filename starts with '_'
@@ -1039,7 +3937,7 @@ fn get_md_files_recursive(path: &Path) -> Vec<String> {
                     }
                     
                     // Skip if it's an ignored directory
-                    if path.is_dir() && path.file_name()
+                    if skip_project_dirs && path.is_dir() && path.file_name()
                         .and_then(|n| n.to_str())
                         .map(|name| IGNORED_DIRS.contains(&name))
                         .unwrap_or(false)
@@ -1047,10 +3945,14 @@ fn get_md_files_recursive(path: &Path) -> Vec<String> {
                         return Vec::new();
                     }
                     
+                    if path.is_dir() && fs::canonicalize(&path).is_ok_and(|dir| dir == output_dir) {
+                        return Vec::new();
+                    }
+                    
                     // Process directory or markdown file
                     if path.is_dir() {
-                        get_md_files_recursive(&path)
-                    } else if path.extension().map_or(false, |ext| ext == "md") {
+                        get_md_files_recursive(&path, skip_project_dirs, extensions, output_dir)
+                    } else if path.extension().is_some_and(|ext| extensions.iter().any(|e| ext == e.as_str())) {
                         path.to_str().map(|s| s.to_string()).into_iter().collect()
                     } else {
                         Vec::new()
@@ -1061,6 +3963,108 @@ fn get_md_files_recursive(path: &Path) -> Vec<String> {
         .unwrap_or_default()
 }
 
+// Increases a heading level by shift, clamped at h6
+fn shift_heading_level(level: HeadingLevel, shift: usize) -> HeadingLevel {
+    HeadingLevel::try_from((level as usize + shift).min(6)).unwrap_or(HeadingLevel::H6)
+}
+
+// Splits on whitespace outside double quotes, stripping quotes that wrap a whole argument
+// ("a b" -> a b) but keeping them inside one (class="x y" stays as-is)
+fn split_quoted_args(s: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    let mut finish = |current: &mut String| {
+        if current.len() >= 2 && current.starts_with('"') && current.ends_with('"') {
+            args.push(current[1..current.len() - 1].to_string());
+        } else if !current.is_empty() {
+            args.push(current.clone());
+        }
+        current.clear();
+    };
+
+    for c in s.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => finish(&mut current),
+            c => current.push(c),
+        }
+    }
+    finish(&mut current);
+    args
+}
+
+// Returns the svg from its root element on (dropping any XML prolog), with attributes added to
+// the root; class values are appended to existing classes, other attributes replace existing ones
+fn inject_svg_attributes(svg: &str, attributes: &[(String, String)]) -> Option<String> {
+    let start = svg.find("<svg")?;
+    let svg = &svg[start..];
+    let mut in_quote = None;
+    let tag_end = svg.char_indices().find_map(|(i, c)| match (c, in_quote) {
+        ('"' | '\'', None) => { in_quote = Some(c); None }
+        (c, Some(q)) if c == q => { in_quote = None; None }
+        ('>', None) => Some(i),
+        _ => None,
+    })?;
+
+    let tag_body = svg["<svg".len()..tag_end].trim_end();
+    let self_closing = tag_body.ends_with('/');
+    let mut existing = parse_tag_attributes(tag_body.trim_end_matches('/'));
+    for (name, value) in attributes {
+        match existing.iter_mut().find(|(n, _)| n == name) {
+            Some((_, Some(current))) if name == "class" => {
+                current.push(' ');
+                current.push_str(value);
+            }
+            Some((_, current)) => *current = Some(value.clone()),
+            None => existing.push((name.clone(), Some(value.clone()))),
+        }
+    }
+
+    let mut tag = String::from("<svg");
+    for (name, value) in existing {
+        match value {
+            Some(value) => tag.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;"))),
+            None => tag.push_str(&format!(" {}", name)),
+        }
+    }
+    tag.push_str(if self_closing { "/>" } else { ">" });
+    Some(tag + &svg[tag_end + 1..])
+}
+
+// Splits the inside of an opening tag into (name, value) pairs; bare attributes have no value
+fn parse_tag_attributes(tag_body: &str) -> Vec<(String, Option<String>)> {
+    let mut attributes = vec![];
+    let mut rest = tag_body.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let name = rest[..name_end].to_string();
+        rest = rest[name_end..].trim_start();
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (value, remaining) = match after_eq.chars().next() {
+                Some(q @ ('"' | '\'')) => {
+                    let end = after_eq[1..].find(q).map_or(after_eq.len(), |e| e + 1);
+                    (&after_eq[1..end], after_eq.get(end + 1..).unwrap_or(""))
+                }
+                _ => {
+                    let end = after_eq.find(char::is_whitespace).unwrap_or(after_eq.len());
+                    (&after_eq[..end], &after_eq[end..])
+                }
+            };
+            attributes.push((name, Some(value.to_string())));
+            rest = remaining.trim_start();
+        } else {
+            attributes.push((name, None));
+        }
+    }
+    attributes
+}
+
 fn file_path_stem(base_path: &Path, full_path: &str) -> String {
     Path::new(full_path).strip_prefix(base_path)
     .map(|p| p.to_string_lossy().into_owned())
@@ -1083,7 +4087,22 @@ fn has_protocol(url: &str) -> bool {
     }
 }
 
-fn copy_assets(src: &str, dst: &str, verbose: bool) -> Result<(), Box<dyn Error>> {
+// Replaces the scheme and host of an absolute URL, keeping its path
+fn with_canonical_host(url: &str, canonical_host: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    let canonical_host = canonical_host.trim_end_matches('/');
+
+    if canonical_host.contains("://") {
+        format!("{}{}", canonical_host, path)
+    } else {
+        format!("{}://{}{}", scheme, canonical_host, path)
+    }
+}
+
+fn copy_assets(src: &str, dst: &str, compile_scss: bool, verbose: bool) -> Result<(), Box<dyn Error>> {
     if !Path::new(src).exists() {
         println!("input assets dir {} does not exist", src);
         return Ok(());
@@ -1092,41 +4111,191 @@ fn copy_assets(src: &str, dst: &str, verbose: bool) -> Result<(), Box<dyn Error>
         return Ok(());
     }
     
-    create_dir(&Path::new(dst), verbose)?;
+    create_dir(Path::new(dst), verbose)?;
     for entry in fs::read_dir(src)? {
         let entry = entry?;
         let path = entry.path();
         let dest_path = Path::new(dst).join(entry.file_name());
         
         if path.is_dir() {
-            copy_assets(path.to_str().unwrap(), dest_path.to_str().unwrap(), verbose)?;
+            copy_assets(path.to_str().unwrap(), dest_path.to_str().unwrap(), compile_scss, verbose)?;
+        } else if compile_scss && is_scss_file(&path) {
+            // Partials (_name.scss) are only compiled through the files importing them
+            if !entry.file_name().to_string_lossy().starts_with('_') {
+                write_if_changed(&dest_path.with_extension("css"), &compile_scss_file(&path)?.0, verbose)?;
+            }
         } else {
-            fs::copy(path, dest_path)?;
+            copy_if_changed(&path, &dest_path, verbose)?;
         }
     }
     Ok(())
 }
 
-fn load_yaml_data(path: &str) -> Result<Value, Box<dyn Error>> {
-    let file = File::open(path)
-        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
-    serde_yaml::from_reader(file)
-        .map_err(|e| format!("Failed to parse YAML in {}: {}", path, e).into())
+fn is_scss_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "scss" || extension == "sass")
 }
 
-// Helper function to deep merge two YAML values
-fn merge_yaml_values(mut primary: Value, secondary: Value) -> Value {
-    if let Value::Mapping(ref mut map1) = primary {
-        if let Value::Mapping(map2) = secondary {
-            for (k, v) in map2 {
-                map1.insert(k, v);
-            }
+// Compiles a .scss or .sass file to CSS with grass, which resolves imports relative to the file
+// and picks the indented syntax from the .sass extension
+// Reads files from disk for grass, keeping track of each one it read
+#[derive(Debug, Default)]
+struct RecordingFs {
+    read: RefCell<Vec<PathBuf>>,
+}
+
+impl grass::Fs for RecordingFs {
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.read.borrow_mut().push(path.to_path_buf());
+        fs::read(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+}
+
+// The compiled CSS of a stylesheet and every file it was compiled from, the stylesheet first
+fn compile_scss_file(path: &Path) -> Result<(String, Vec<PathBuf>), Box<dyn Error>> {
+    let recording_fs = RecordingFs::default();
+    let css = grass::from_path(path, &grass::Options::default().fs(&recording_fs)).map_err(|e| {
+        // grass reports the message, a source excerpt and then the file:line:column it points at
+        let report = e.to_string();
+        let message = report.lines().next().unwrap_or_default().trim_start_matches("Error: ");
+        match report.lines().last().filter(|_| report.lines().count() > 1) {
+            Some(location) => format!("could not compile {}: {} ({})", path.display(), message, location.trim().trim_start_matches("./")),
+            None => format!("could not compile {}: {}", path.display(), message),
+        }
+    })?;
+    let mut sources = vec![path.to_path_buf()];
+    for source in recording_fs.read.into_inner() {
+        if !sources.contains(&source) {
+            sources.push(source);
         }
     }
-    primary
+    Ok((css, sources))
 }
 
-pub fn generate_robots_txt(
+// Writes content unless the file already holds exactly that, keeping its modification time
+fn write_if_changed(path: &Path, content: &str, verbose: bool) -> Result<(), Box<dyn Error>> {
+    if fs::read_to_string(path).is_ok_and(|existing| existing == content) {
+        if verbose {
+            println!("{}: skipped (unchanged)", path.display());
+        }
+        return Ok(());
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+// Copies a file unless the destination already has the same size and is at least as new, so
+// rebuilds don't rewrite every asset
+fn copy_if_changed(src: &Path, dst: &Path, verbose: bool) -> Result<(), Box<dyn Error>> {
+    let src_metadata = fs::metadata(src)?;
+    let unchanged = fs::metadata(dst).is_ok_and(|dst_metadata| {
+        dst_metadata.len() == src_metadata.len()
+            && matches!((dst_metadata.modified(), src_metadata.modified()), (Ok(dst_time), Ok(src_time)) if dst_time >= src_time)
+    });
+    if unchanged {
+        if verbose {
+            println!("{}: skipped (unchanged)", dst.display());
+        }
+    } else {
+        fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+// Fingerprinted names of assets by their path under assets/, and the files each compiled
+// stylesheet was made from
+type AssetFingerprints = (BTreeMap<String, String>, BTreeMap<String, Vec<PathBuf>>);
+
+// Fingerprinted name of every file under the assets directory by its path there, e.g.
+// css/style.css -> css/style.1a2b3c4d.css, with the hash taken from the file's contents. With
+// compile_scss, stylesheets are listed by their compiled .css name and hashed from the compiled
+// CSS, so editing an imported partial changes the name too
+fn asset_fingerprints(assets_dir: &Path, compile_scss: bool) -> Result<AssetFingerprints, Box<dyn Error>> {
+    fn walk(dir: &Path, prefix: &str, compile_scss: bool, fingerprints: &mut AssetFingerprints) -> Result<(), Box<dyn Error>> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let mut name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if path.is_dir() {
+                walk(&path, &format!("{}{}/", prefix, name), compile_scss, fingerprints)?;
+                continue;
+            }
+            let contents = if compile_scss && is_scss_file(&path) {
+                if name.starts_with('_') {
+                    continue;
+                }
+                name = path.with_extension("css").file_name().unwrap().to_string_lossy().into_owned();
+                let (css, sources) = compile_scss_file(&path)?;
+                fingerprints.1.insert(format!("{}{}", prefix, name), sources);
+                css.into_bytes()
+            } else {
+                fs::read(&path)?
+            };
+            let relative = format!("{}{}", prefix, name);
+            let hash = content_hash(&contents);
+            let fingerprinted = match name.rsplit_once('.') {
+                Some((stem, extension)) if !stem.is_empty() => format!("{}{}.{}.{}", prefix, stem, &hash[..8], extension),
+                _ => format!("{}.{}", relative, &hash[..8]),
+            };
+            fingerprints.0.insert(relative, fingerprinted);
+        }
+        Ok(())
+    }
+
+    let mut fingerprints = (BTreeMap::new(), BTreeMap::new());
+    if assets_dir.is_dir() {
+        walk(assets_dir, "", compile_scss, &mut fingerprints)?;
+    }
+    Ok(fingerprints)
+}
+
+// Copies each copied asset to its fingerprinted name as well, so plain links keep working, and
+// writes the original -> fingerprinted mapping to assets/asset-manifest.json
+fn write_fingerprinted_assets(verbose: bool, output_base: &Path, manifest: &BTreeMap<String, String>) -> Result<(), Box<dyn Error>> {
+    let assets_output = output_base.join("assets");
+    create_dir(&assets_output, verbose)?;
+    for (original, fingerprinted) in manifest {
+        if verbose {
+            println!("fingerprinting assets/{} as assets/{}", original, fingerprinted);
+        }
+        copy_if_changed(&assets_output.join(original), &assets_output.join(fingerprinted), verbose)?;
+    }
+    fs::write(assets_output.join(ASSET_MANIFEST_PATH), serde_json::to_string_pretty(manifest)?)?;
+    Ok(())
+}
+
+const ASSET_MANIFEST_PATH: &str = "asset-manifest.json";
+
+fn load_yaml_data(path: &str) -> Result<Value, Box<dyn Error>> {
+    let file = File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    serde_yaml::from_reader(file)
+        .map_err(|e| format!("Failed to parse YAML in {}: {}", path, e).into())
+}
+
+// Helper function to deep merge two YAML values
+fn merge_yaml_values(mut primary: Value, secondary: Value) -> Value {
+    if let Value::Mapping(ref mut map1) = primary {
+        if let Value::Mapping(map2) = secondary {
+            for (k, v) in map2 {
+                map1.insert(k, v);
+            }
+        }
+    }
+    primary
+}
+
+pub fn generate_robots_txt(
     config: &RobotsConfig,
     html_files: &[PathBuf],
     output_dir: &PathBuf,
@@ -1205,7 +4374,7 @@ pub fn generate_robots_txt(
     if config.auto_disallow_non_included_html.unwrap_or(false) {
         // Auto-disallow any HTML files not marked for inclusion
         let allowed_paths = get_all_allowed_paths(config);
-        let disallowed_html = find_disallowed_html(html_files, &allowed_paths, &output_dir);
+        let disallowed_html = find_disallowed_html(html_files, &allowed_paths, output_dir);
         
         if !disallowed_html.is_empty() {
             robots.push_str("# Auto-disallowed generated files\n");
@@ -1283,428 +4452,3139 @@ fn find_disallowed_html(
         .collect()
 }
 
-fn generate_and_write_sitemap_xml(verbose: bool, output_base: &PathBuf, sitemap_xml_nodes: Vec<SitemapXmlNode>) -> Result<(), Box<dyn Error>> {
-    if verbose {
-        println!("generating sitemap.xml");
-    }
-    let sitemap_xml = SitemapXmlNode::generate_sitemap_xml(&sitemap_xml_nodes);
-    let output_path = output_base.join("assets/sitemap.xml");
-    fs::write(output_path, sitemap_xml)?;
-    Ok(())
+// Normalizes a site path so "/old/", "old" and "old/" compare equal
+fn normalize_redirect_path(path: &str) -> String {
+    path.trim().trim_matches('/').to_string()
 }
 
-fn generate_and_write_robots_txt(verbose: bool, output_base: &PathBuf, output_html_paths: Vec<PathBuf>, robots_config: RobotsConfig) -> Result<(), Box<dyn Error>> {
-    if verbose {
-        println!("Generating robots.txt");
+// Output file for a redirect source: paths with an extension are written as-is, others get an
+// index file (index.html unless index_filename changes it)
+fn redirect_output_path(from: &str, index_file: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = PathBuf::from(normalize_redirect_path(from));
+    if path.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(format!("redirect from {} in data/redirects.yaml must stay inside the output directory", from).into());
+    }
+    if path.extension().is_some() {
+        Ok(path)
+    } else {
+        Ok(path.join(index_file))
     }
-    let content = generate_robots_txt(&robots_config, &output_html_paths, output_base);
-    let output_path = output_base.join("assets/robots.txt");
-    fs::write(output_path, content)?;
-    Ok(())
 }
 
-// ========== Main Function ==========
+fn generate_redirect_html(to: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Redirecting...</title>\n<link rel=\"canonical\" href=\"{to}\">\n<meta http-equiv=\"refresh\" content=\"0; url={to}\">\n<meta name=\"robots\" content=\"noindex\">\n</head>\n<body>\n<p>Redirecting to <a href=\"{to}\">{to}</a>.</p>\n</body>\n</html>\n"
+    )
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
-    
-    // Load config file if specified
-    let config = if let Some(config_path) = &cli.config {
-        Config::from_file(config_path)?
-    } else {
-        // Try default config locations
-        let default_config_path = std::env::current_dir().unwrap().join("meowdown-config.yaml");
-        if std::fs::exists(&default_config_path)? {
-            Config::from_file(&default_config_path)?
-        } else {
-            // or create empty
-            Config::default()
-        }
-    };
-    
-    if cli.verbose {
-        println!("Starting with config: {:#?}", config);
-    }
+// Warns about redirects that point at other redirects, and about loops among them
+fn check_redirect_chains(redirects: &[(String, String)]) {
+    let targets = redirects.iter()
+        .map(|(from, to)| (normalize_redirect_path(from), normalize_redirect_path(to)))
+        .collect::<HashMap<_, _>>();
 
-    match &cli.command {
-        Some(Commands::Build { clean }) => {
-            if *clean {
-                clean_output_dir(&config)?;
+    for (from, to) in redirects {
+        let start = normalize_redirect_path(from);
+        let mut visited = vec![start.clone()];
+        let mut current = normalize_redirect_path(to);
+        while let Some(next) = targets.get(&current) {
+            if visited.contains(&current) {
+                eprintln!("Warning: redirect loop detected: {} -> {}", visited.join(" -> "), current);
+                break;
             }
-            build_site_for_each_variant(&config, cli.verbose)?;
-        }
-        Some(Commands::Clean { }) => {
-            clean_output_dir(&config)?;
+            visited.push(current.clone());
+            current = next.clone();
         }
-        Some(Commands::Watch { }) => {
-            watch_and_rebuild(&config, cli.verbose)?;
+
+        if visited.len() > 1 && !visited.contains(&current) {
+            eprintln!("Warning: redirect chain detected: {} -> {}, consider pointing {} directly at {}", visited.join(" -> "), current, from, current);
         }
-        Some(Commands::New { name, default }) => {
-            create_new_project(name, *default, cli.verbose)?;
+    }
+}
+
+fn generate_and_write_redirects(
+    verbose: bool,
+    global_context: &GlobalContext,
+    output_base: &Path,
+    output_html_paths: &[PathBuf],
+    redirects: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    check_redirect_chains(redirects);
+
+    // A --since build only writes some pages, so check against every page the site has too
+    let include_drafts = global_context.cfg.drafts.unwrap_or(false) || global_context.cfg.preview.unwrap_or(false);
+    let site_pages = global_context.pages.iter()
+        .filter(|page| include_drafts || !is_draft(&page.front_matter))
+        .filter_map(|page| page.output_path.strip_prefix(output_base).ok());
+    let pages = output_html_paths.iter()
+        .map(PathBuf::as_path)
+        .chain(site_pages)
+        .map(|p| normalize_redirect_path(&p.to_string_lossy()))
+        .collect::<BTreeSet<_>>();
+
+    for (from, to) in redirects {
+        let relative_path = redirect_output_path(from, &global_context.cfg.index_file_name())?;
+        if pages.contains(&normalize_redirect_path(&relative_path.to_string_lossy())) {
+            eprintln!("Warning: redirect from {} conflicts with a generated page, skipping", from);
+            continue;
         }
-        None => {
-            // Default to build command
-            build_site_for_each_variant(&config, cli.verbose)?;
+
+        let output_path = output_base.join(&relative_path);
+        if verbose {
+            println!("writing redirect {} -> {} to {}", from, to, output_path.display());
         }
+        create_dir(output_path.parent().unwrap(), verbose)?;
+        fs::write(output_path, generate_redirect_html(&global_context.relative_url(to)))?;
     }
     Ok(())
 }
 
-pub fn watch_and_rebuild(
-    config: &Config,
-    verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔭 {} {}", 
-        "Watching for changes...",
-        "(Press Ctrl+C to stop)"
-    );
+// Appends an XML line, indented by depth and newline-terminated when pretty, bare otherwise
+fn push_xml_line(xml: &mut String, pretty: bool, depth: usize, line: &str) {
+    if pretty {
+        xml.push_str(&"  ".repeat(depth));
+        xml.push_str(line);
+        xml.push('\n');
+    } else {
+        xml.push_str(line);
+    }
+}
 
-    // Create channel for file change events
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher: RecommendedWatcher = Watcher::new(
-        tx, 
-        notify::Config::default()
-            .with_poll_interval(Duration::from_secs(1)) // Debounce time
-    )?;
+// The entry in a data file whose match key equals value: a list entry with that key field (id, then
+// slug, unless a key is given) or, in a mapping of entries, the entry under that name
+fn find_data_entry<'a>(data: &'a Value, value: &str, key: Option<&str>) -> Option<&'a serde_yaml::Mapping> {
+    let keys = match key {
+        Some(key) => vec![key],
+        None => vec!["id", "slug"],
+    };
+    let matches = |entry: &serde_yaml::Mapping| keys.iter()
+        .any(|key| entry.get(*key).and_then(front_matter_scalar).is_some_and(|v| v == value));
+    match data {
+        Value::Sequence(entries) => entries.iter()
+            .filter_map(Value::as_mapping)
+            .find(|entry| matches(entry)),
+        Value::Mapping(entries) => entries.get(value)
+            .and_then(Value::as_mapping)
+            .or_else(|| entries.values().filter_map(Value::as_mapping).find(|entry| matches(entry))),
+        _ => None,
+    }
+}
 
-    // Watch relevant directories
-    let watch_dirs = [
-        config.full_input_path(),
-        PathBuf::from("assets"),
+// Plain text from HTML: tags are dropped, block elements and <br> become line breaks, list items
+// become "- " lines, common entities are decoded and runs of blank lines are collapsed
+fn strip_html(html: &str) -> String {
+    const BLOCK_ELEMENTS: &[&str] = &[
+        "p", "div", "br", "li", "ul", "ol", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre", "tr", "table", "hr",
     ];
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            rest = &rest[open..];
+            break;
+        };
+        let name = rest[open + 1..open + close].trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        if name == "li" && !rest[open + 1..].starts_with('/') {
+            text.push_str("\n- ");
+        } else if BLOCK_ELEMENTS.contains(&name.as_str()) {
+            text.push('\n');
+        }
+        rest = &rest[open + close + 1..];
+    }
+    text.push_str(rest);
 
-    for dir in watch_dirs {
-        if dir.exists() {
-            watcher.watch(&dir, notify::RecursiveMode::Recursive)?;
-            if verbose {
-                println!("👀 {}", format!("Watching: {}", dir.display()));
-            }
+    let text = text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    let mut output = String::new();
+    let mut blank_lines = 0;
+    let mut previous_was_item = false;
+    for line in text.lines().map(str::trim_end) {
+        if line.trim().is_empty() || line == "-" {
+            blank_lines += 1;
+            continue;
         }
+        let is_item = line.starts_with("- ");
+        if !output.is_empty() {
+            let paragraph_break = blank_lines > 0 && !(is_item && previous_was_item);
+            output.push_str(if paragraph_break { "\n\n" } else { "\n" });
+        }
+        output.push_str(line);
+        blank_lines = 0;
+        previous_was_item = is_item;
     }
+    output
+}
 
-    // Track last build time to avoid rapid rebuilds
-    let mut last_build = std::time::Instant::now();
-    let min_rebuild_interval = Duration::from_secs(2);
+// Minifies a rendered page with minify-html, keeping closing tags, the <html> and <head> tags and
+// the doctype as written, and leaving inline scripts and styles alone
+fn minify_html(html: &str) -> String {
+    let cfg = minify_html::Cfg {
+        keep_closing_tags: true,
+        keep_html_and_head_opening_tags: true,
+        ..minify_html::Cfg::spec_compliant()
+    };
+    String::from_utf8_lossy(&minify_html::minify(html.as_bytes(), &cfg)).into_owned()
+}
 
-    loop {
-        match rx.recv() {
-            Ok(Ok(notify::Event { kind: notify::EventKind::Modify(_), paths, .. })) => {
-                // Filter relevant changes
-                if should_trigger_rebuild(&paths) && last_build.elapsed() > min_rebuild_interval {
-                    if verbose {
-                        println!("\n📡 {} {:?}", 
-                            "Change detected in:",
-                            paths.iter().map(|p| p.display()).collect::<Vec<_>>()
-                        );
-                    }
+// Cuts HTML after max_chars characters of text (an entity counts as one), backing up to the last
+// word break and closing whatever tags are still open. HTML that already fits is returned as-is.
+fn truncate_html(html: &str, max_chars: usize) -> String {
+    const VOID_ELEMENTS: &[&str] = &[
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+    ];
+    let has_visible_text = |rest: &str| {
+        let mut in_tag = false;
+        rest.chars().any(|c| match c {
+            '<' => { in_tag = true; false }
+            '>' => { in_tag = false; false }
+            c => !in_tag && !c.is_whitespace(),
+        })
+    };
 
-                    match build_site_for_each_variant(config, verbose) {
-                        Ok(_) => {
-                            println!("✅ {}", "Rebuild successful!");
-                            last_build = std::time::Instant::now();
-                        }
-                        Err(e) => {
-                            println!("❌ {}: {}", "Build failed", e);
-                        }
-                    }
+    let mut output = String::new();
+    let mut open_tags: Vec<String> = vec![];
+    let mut visible = 0;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = if rest.starts_with("<!--") {
+                rest.find("-->").map(|pos| pos + 3)
+            } else {
+                rest.find('>').map(|pos| pos + 1)
+            };
+            let Some(end) = end else {
+                break;
+            };
+            let tag = &rest[..end];
+            if let Some(closing) = tag.strip_prefix("</") {
+                let name = closing.trim_end_matches('>').trim().to_ascii_lowercase();
+                if let Some(pos) = open_tags.iter().rposition(|open| *open == name) {
+                    open_tags.truncate(pos);
+                    output.push_str(tag);
+                }
+            } else {
+                let name = tag[1..].split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                let opens = !name.is_empty() && !tag.starts_with("<!") && !tag.starts_with("<?")
+                    && !tag.ends_with("/>") && !VOID_ELEMENTS.contains(&name.as_str());
+                if opens {
+                    open_tags.push(name);
                 }
+                output.push_str(tag);
             }
-            Ok(Err(e)) => println!("⚠️ {}: {}", "Watch error", e),
-            _ => {}
+            rest = &rest[end..];
+            continue;
         }
+
+        if visible >= max_chars && has_visible_text(rest) {
+            // Back up to a word break in the current run of text, if there is one
+            let text_start = output.rfind('>').map_or(0, |pos| pos + 1);
+            if let Some(space) = output[text_start..].rfind(char::is_whitespace) {
+                output.truncate(text_start + space);
+            }
+            let trimmed_len = output.trim_end().len();
+            output.truncate(trimmed_len);
+            output.push('…');
+            for tag in open_tags.iter().rev() {
+                output.push_str(&format!("</{}>", tag));
+            }
+            return output;
+        }
+
+        let len = match rest.find(';') {
+            Some(pos) if c == '&' && pos <= 10 => pos + 1,
+            _ => c.len_utf8(),
+        };
+        output.push_str(&rest[..len]);
+        rest = &rest[len..];
+        visible += 1;
     }
+    output
 }
 
-fn should_trigger_rebuild(paths: &[PathBuf]) -> bool {
-    paths.iter().any(|p| {
-        // Only trigger for these file types
-        match p.extension().and_then(|e| e.to_str()) {
-            Some("md" | "tpl" | "html" | "css" | "js" | "yml" | "yaml" | "json" | "csv") => true,
-            _ => false
-        }
-    })
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
 }
 
-pub fn create_new_project(
-    name: &str,
-    use_default_template: bool,
-    verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let project_dir = std::env::current_dir().unwrap().join(name);
-    println!("creating new project at {:?}", project_dir);
-    
-    // Create project directory structure
-    create_dir(&project_dir, verbose)?;
-    create_dir(&project_dir.join("assets"), verbose)?;
-    create_dir(&project_dir.join("data"), verbose)?;
-    create_dir(&project_dir.join("templates"), verbose)?;
+fn parse_changefreq(changefreq: &str, page_path: &str) -> Option<ChangeFrequency> {
+    changefreq.trim().to_lowercase().parse::<ChangeFrequency>()
+        .map_err(|_| eprintln!("Warning: invalid changefreq {} in {}, using the default", changefreq, page_path))
+        .ok()
+}
 
-    // Create default files
-    create_file(
-        &project_dir.join("data/site.yaml"),
-        include_str!("../_default-data/data/default_site.yaml"),
-        verbose,
-    )?;
+// Where the sitemap and robots.txt are written by default, relative to the output root
+const DEFAULT_SITEMAP_PATH: &str = "sitemap.xml";
+const DEFAULT_ROBOTS_PATH: &str = "robots.txt";
+const OPENSEARCH_PATH: &str = "opensearch.xml";
+const RSS_FEED_PATH: &str = "assets/feed.xml";
 
-    create_file(
-        &project_dir.join("meowdown-config.yaml"),
-        include_str!("../_default-data/default_meowdown-config.yaml"),
-        verbose,
-    )?;
+fn generate_and_write_sitemap_xml(verbose: bool, pretty: bool, output_base: &Path, sitemap_path: &str, sitemap_xml_nodes: Vec<SitemapXmlNode>) -> Result<(), Box<dyn Error>> {
+    if verbose {
+        println!("generating {}", sitemap_path);
+    }
+    let sitemap_xml = SitemapXmlNode::generate_sitemap_xml(&sitemap_xml_nodes, pretty);
+    let output_path = output_base.join(sitemap_path);
+    create_dir(output_path.parent().unwrap(), verbose)?;
+    fs::write(output_path, sitemap_xml)?;
+    Ok(())
+}
 
-    create_file(
-        &project_dir.join("index.md"),
-        include_str!("../_default-data/default_index.md"),
-        verbose,
-    )?;
+fn generate_and_write_robots_txt(verbose: bool, output_base: &PathBuf, robots_path: &str, output_html_paths: Vec<PathBuf>, robots_config: RobotsConfig) -> Result<(), Box<dyn Error>> {
+    if verbose {
+        println!("Generating {}", robots_path);
+    }
+    let content = generate_robots_txt(&robots_config, &output_html_paths, output_base);
+    let output_path = output_base.join(robots_path);
+    create_dir(output_path.parent().unwrap(), verbose)?;
+    fs::write(output_path, content)?;
+    Ok(())
+}
 
-    if use_default_template {
-        create_file(
-            &project_dir.join("templates/default.tpl.html"),
-            include_str!("../_default-data/templates/default_layout.tpl.html"),
-            verbose,
-        )?;
-        
-        create_file(
-            &project_dir.join("assets/style.css"),
-            include_str!("../_default-data/assets/default_style.css"),
-            verbose,
-        )?;
+// humans.txt comes from the humans_txt config value, or data/humans.txt next to the config
+fn generate_and_write_humans_txt(verbose: bool, config: &Config, output_base: &Path) -> Result<(), Box<dyn Error>> {
+    if verbose {
+        println!("Generating humans.txt");
     }
+    let content = match &config.humans_txt {
+        Some(content) => content.clone(),
+        None => {
+            let path = config.relative_to_config_path(&PathBuf::from("data/humans.txt"));
+            fs::read_to_string(&path)
+                .map_err(|e| format!("generate_humans_txt is set but neither humans_txt nor {} is available: {}", path.display(), e))?
+        }
+    };
+    fs::write(output_base.join("humans.txt"), content)?;
+    Ok(())
+}
 
-    println!("✨ Created new project '{}' successfully!", name);
+pub fn generate_security_txt(config: &SecurityTxtConfig) -> Result<String, Box<dyn Error>> {
+    if config.contact.is_empty() {
+        return Err("security_txt needs at least one contact".into());
+    }
+    for contact in &config.contact {
+        if !["mailto:", "https:", "tel:"].iter().any(|scheme| contact.starts_with(scheme)) {
+            return Err(format!("security_txt contact {} must be a mailto:, https: or tel: URI", contact).into());
+        }
+    }
 
-    if !use_default_template {
-        println!("⚠️ No templates were included. Add your own in templates/");
+    let expires = DateTime::parse_from_rfc3339(config.expires.trim())
+        .map_err(|e| format!("security_txt expires {} is not an RFC 3339 timestamp: {}", config.expires, e))?
+        .with_timezone(&Utc);
+    if expires < Utc::now() {
+        eprintln!("Warning: security_txt expires {} is in the past", config.expires);
+    } else if expires > Utc::now() + chrono::Duration::days(366) {
+        eprintln!("Warning: security_txt expires {} is more than a year away, which RFC 9116 advises against", config.expires);
     }
 
-    Ok(())
-}
+    let mut txt = String::new();
+    for contact in &config.contact {
+        txt.push_str(&format!("Contact: {}\n", contact));
+    }
+    txt.push_str(&format!("Expires: {}\n", expires.to_rfc3339()));
+    for key in config.encryption.iter().flatten() {
+        txt.push_str(&format!("Encryption: {}\n", key));
+    }
 
-// Helper function to create directory with verbose output
-fn create_dir(path: &Path, verbose: bool) -> std::io::Result<()> {
-    if verbose {
-        println!("Creating directory: {}", path.display());
+    let optional_fields = [
+        ("Acknowledgments", &config.acknowledgments),
+        ("Preferred-Languages", &config.preferred_languages),
+        ("Policy", &config.policy),
+        ("Hiring", &config.hiring),
+        ("Canonical", &config.canonical),
+    ];
+    for (field, value) in optional_fields {
+        if let Some(value) = value {
+            txt.push_str(&format!("{}: {}\n", field, value));
+        }
     }
-    fs::create_dir_all(path)
+    Ok(txt)
 }
 
-// Helper function to create file with verbose output
-fn create_file(path: &Path, content: &str, verbose: bool) -> std::io::Result<()> {
+fn generate_and_write_security_txt(verbose: bool, config: &SecurityTxtConfig, output_base: &Path) -> Result<(), Box<dyn Error>> {
     if verbose {
-        println!("Creating file: {}", path.display());
+        println!("Generating .well-known/security.txt");
     }
-    let mut file = File::create(path)?;
-    file.write_all(content.as_bytes())?;
+    let content = generate_security_txt(config)?;
+    let output_dir = output_base.join(".well-known");
+    create_dir(&output_dir, verbose)?;
+    fs::write(output_dir.join("security.txt"), content)?;
     Ok(())
 }
 
-fn clean_output_dir(config: &Config) -> Result<(), Box<dyn Error>> {
-    let output = config.full_output_path();
-    fs::remove_dir_all(output)
-        .map_err(|e| e.into())
-}
+fn generate_opensearch_xml(config: &OpenSearchConfig, global_context: &GlobalContext, pretty: bool) -> Result<String, Box<dyn Error>> {
+    let short_name = config.short_name.trim();
+    if short_name.is_empty() {
+        return Err("opensearch needs a short_name".into());
+    }
+    if short_name.chars().count() > 16 {
+        eprintln!("Warning: opensearch short_name {} is longer than 16 characters, browsers may cut it off", short_name);
+    }
+    if !config.search_url.contains("{searchTerms}") {
+        return Err(format!("opensearch search_url {} must contain {{searchTerms}} where the query goes", config.search_url).into());
+    }
 
-fn build_site_for_each_variant(config: &Config, verbose: bool) -> Result<(), Box<dyn Error>> {
-    if config.variant.is_some() {
-        if config.variants.is_some() {
-            panic!("Cannot specify both variant and variants in {:?}", config.config_path);
-        } else {
-            build_site(config, verbose)
-        }
-    } else if let Some(variants) = &config.variants {
-        for variant in variants {
-            let cfg_variant = Config { variant: Some(variant.clone()), variants: None, .. config.clone() };
-            build_site(&cfg_variant, verbose)?;
-        }
-        Ok(())
-    } else {
-        build_site(config, verbose)
+    let description = config.description.as_deref().unwrap_or(short_name);
+    let mut xml = String::new();
+    push_xml_line(&mut xml, pretty, 0, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    push_xml_line(&mut xml, pretty, 0, "<OpenSearchDescription xmlns=\"http://a9.com/-/spec/opensearch/1.1/\">");
+    push_xml_line(&mut xml, pretty, 1, &format!("<ShortName>{}</ShortName>", escape_xml(short_name)));
+    push_xml_line(&mut xml, pretty, 1, &format!("<Description>{}</Description>", escape_xml(description)));
+    push_xml_line(&mut xml, pretty, 1, "<InputEncoding>UTF-8</InputEncoding>");
+    if let Some(icon) = &config.icon {
+        push_xml_line(&mut xml, pretty, 1, &format!(
+            "<Image type=\"{}\">{}</Image>",
+            mime_type_for_path(Path::new(icon)),
+            escape_xml(&global_context.relative_url(icon))
+        ));
     }
+    push_xml_line(&mut xml, pretty, 1, &format!(
+        "<Url type=\"text/html\" method=\"get\" template=\"{}\"/>",
+        escape_xml(&global_context.relative_url(&config.search_url))
+    ));
+    push_xml_line(&mut xml, pretty, 0, "</OpenSearchDescription>");
+    Ok(xml)
 }
 
-fn build_site(config: &Config, verbose: bool) -> Result<(), Box<dyn Error>> {
-    let output_base = config.full_output_path();
+fn generate_and_write_opensearch_xml(verbose: bool, pretty: bool, config: &OpenSearchConfig, global_context: &GlobalContext, output_base: &Path) -> Result<(), Box<dyn Error>> {
     if verbose {
-        println!("outputting to {}", output_base.to_str().unwrap());
+        println!("Generating {}", OPENSEARCH_PATH);
     }
+    let content = generate_opensearch_xml(config, global_context, pretty)?;
+    fs::write(output_base.join(OPENSEARCH_PATH), content)?;
+    Ok(())
+}
 
-    let mut global_context = GlobalContext::new_with_defaults(config.clone());
-    create_dir(&output_base, verbose)?;
-    
-    // Build and render all pages
-    let mut output_html_paths = vec![];
-    let mut sitemap_xml_nodes = vec![];
-    for path in get_md_files_recursive(&config.full_input_path())
-        .into_iter()
-        .filter(|p| !p.contains("/assets/") && !p.contains("assets/"))
-    {
-        let page = global_context.build_page(&path)?;
-        
-        if verbose {
-            // Print the tree structure
-            page.print_tree(0);
-        }
-        
-        if let TemplateNode::Page { path, output_path, front_matter, .. } = &*page {
-            let ctx = TemplateContext::new(None);
-            ctx.borrow_mut().add_front_matter(front_matter);
-            
-            create_dir(output_path.parent().unwrap(), verbose)?;
-
-            if verbose {
-                println!("writing html to {}", output_path.to_str().unwrap());
-            }
-            let relative_path = PathBuf::from(&output_path.to_str().unwrap()[output_base.to_str().unwrap().len()..]);
-            output_html_paths.push(relative_path.clone());
-
-            let lastmod = fs::File::open(path)
-                .map(|f| f.metadata().map(|t| t.modified().ok()).ok()).ok()
-                .flatten().flatten();
-            sitemap_xml_nodes.push(SitemapXmlNode {
-                changefreq: Some(ChangeFrequency::Monthly),
-                loc: global_context.relative_url(relative_path.to_str().unwrap()),
-                lastmod: lastmod.map(|x| x.into()),
-                priority: None,
-                alternates: vec![],
-            });
-            fs::write(output_path, page.render(ctx, &mut global_context))?;
-        } else {
-            panic!("could not build page {}", path);
-        }
+// RSS 2.0 feed of the posts (pages under posts_dir, or every page), newest first by their date
+// front matter. Items use the description or summary front matter, else the start of the page's text
+fn generate_rss_xml(global_context: &GlobalContext, pretty: bool) -> Result<String, Box<dyn Error>> {
+    let include_future = global_context.cfg.preview.unwrap_or(false);
+    let mut posts = global_context.posts().into_iter()
+        .filter(|page| include_future || !is_future_dated(&page.front_matter))
+        .map(|page| (page.front_matter.get("date").and_then(FrontMatterValue::as_str).and_then(parse_date), page))
+        .collect::<Vec<_>>();
+    // Undated posts go last, keeping their path order
+    posts.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    let site_string = |key: &str| global_context.site_strings.get(key).cloned().unwrap_or_default();
+    let mut xml = String::new();
+    push_xml_line(&mut xml, pretty, 0, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    push_xml_line(&mut xml, pretty, 0, "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">");
+    push_xml_line(&mut xml, pretty, 1, "<channel>");
+    push_xml_line(&mut xml, pretty, 2, &format!("<title>{}</title>", escape_xml(&site_string("site.title"))));
+    push_xml_line(&mut xml, pretty, 2, &format!("<link>{}</link>", escape_xml(&global_context.relative_url(""))));
+    push_xml_line(&mut xml, pretty, 2, &format!("<description>{}</description>", escape_xml(&site_string("site.description"))));
+    push_xml_line(&mut xml, pretty, 2, &format!(
+        "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>",
+        escape_xml(&global_context.relative_url(RSS_FEED_PATH))
+    ));
+    if let Some(lang) = global_context.site_strings.get("site.lang") {
+        push_xml_line(&mut xml, pretty, 2, &format!("<language>{}</language>", escape_xml(lang)));
     }
-    
-    copy_assets(
-        config.relative_to_config_path(&PathBuf::from("assets")).to_str().unwrap(), 
-        output_base.join("assets").to_str().unwrap(), 
-        verbose
-    )?;
 
-    match global_context.load_robots_config()? {
-        Some(robots_config) if config.generate_robots_txt.unwrap_or(false) => {
-            generate_and_write_sitemap_xml(verbose, &output_base, sitemap_xml_nodes)?;
-            generate_and_write_robots_txt(verbose, &output_base, output_html_paths, robots_config)?;
-        },
-        _ if config.generate_sitemap_xml.unwrap_or(false) => {
-            generate_and_write_sitemap_xml(verbose, &output_base, sitemap_xml_nodes)?;
-        },
-        _ => {
-            if verbose {
-                println!("Not generating sitemap.xml or robots.txt");
+    for (date, page) in posts {
+        let title = page.front_matter.get("title").map(ToString::to_string).unwrap_or_default();
+        let link = global_context.page_url(&page.output_path);
+        let description = match page.front_matter.get("description").or_else(|| page.front_matter.get("summary")) {
+            Some(description) => description.to_string(),
+            None => {
+                let (_, markdown) = global_context.load_page_source(&page.source_path)?;
+                rss_excerpt(&strip_html(&render_markdown(&restore_raw_text(protect_raw_markdown(&markdown)))), 200)
             }
+        };
+
+        push_xml_line(&mut xml, pretty, 2, "<item>");
+        push_xml_line(&mut xml, pretty, 3, &format!("<title>{}</title>", escape_xml(&title)));
+        push_xml_line(&mut xml, pretty, 3, &format!("<link>{}</link>", escape_xml(&link)));
+        push_xml_line(&mut xml, pretty, 3, &format!("<guid isPermaLink=\"true\">{}</guid>", escape_xml(&link)));
+        if let Some(date) = date {
+            push_xml_line(&mut xml, pretty, 3, &format!("<pubDate>{}</pubDate>", date.to_rfc2822()));
         }
+        push_xml_line(&mut xml, pretty, 3, &format!("<description>{}</description>", escape_xml(&description)));
+        push_xml_line(&mut xml, pretty, 2, "</item>");
     }
 
-    if let Some(variant) = &config.variant {
-        println!("Site generation for variant {} complete!", variant);
+    push_xml_line(&mut xml, pretty, 1, "</channel>");
+    push_xml_line(&mut xml, pretty, 0, "</rss>");
+    Ok(xml)
+}
+
+// Plain text cut to about max_chars at a word break, with runs of whitespace collapsed
+fn rss_excerpt(text: &str, max_chars: usize) -> String {
+    truncate_chars(&text.split_whitespace().collect::<Vec<_>>().join(" "), max_chars)
+}
+
+// Text cut to at most max_chars characters at the last word boundary, with an ellipsis when
+// anything was cut; a single word longer than that is cut mid-word
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    let Some((cut, _)) = text.char_indices().nth(max_chars) else {
+        return text.to_string();
+    };
+    // A word ending right at the limit is kept whole
+    let cut = if text[cut..].starts_with(char::is_whitespace) {
+        cut
     } else {
-        println!("Site generation complete!");
+        text[..cut].rfind(char::is_whitespace).unwrap_or(cut)
+    };
+    format!("{}…", text[..cut].trim_end())
+}
+
+// Text cut to its first max_words words with an ellipsis, or unchanged when it's no longer than that
+fn truncate_words(text: &str, max_words: usize) -> String {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    if words.len() <= max_words {
+        return text.to_string();
+    }
+    format!("{}…", words[..max_words].join(" "))
+}
+
+fn generate_and_write_rss_xml(verbose: bool, pretty: bool, global_context: &GlobalContext, output_base: &Path) -> Result<(), Box<dyn Error>> {
+    if verbose {
+        println!("Generating {}", RSS_FEED_PATH);
     }
+    let content = generate_rss_xml(global_context, pretty)?;
+    let output_path = output_base.join(RSS_FEED_PATH);
+    create_dir(output_path.parent().unwrap(), verbose)?;
+    fs::write(output_path, content)?;
     Ok(())
 }
 
-#[derive(Parser)]
-#[command(name = "MeowDown")]
-#[command(version = "1.0")]
-#[command(about = "A static site generator", long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Option<Commands>,
-    
-    // Path to config file (optional)
-    #[arg(short, long)]
-    config: Option<PathBuf>,
-    
-    // Verbose output
-    #[arg(short, long)]
-    verbose: bool,
+const BUILD_MANIFEST_PATH: &str = ".meowdown-manifest.json";
+const BUILD_CHANGES_PATH: &str = "changes.json";
+const BUILD_CACHE_PATH: &str = ".meowdown-cache.json";
+
+// 64-bit FNV-1a, stable across platforms and Rust versions unlike DefaultHasher
+fn content_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
 }
 
-#[derive(clap::Subcommand)]
-enum Commands {
-    // Build the site
-    Build {    
-        // Clean output directory before building
-        #[arg(short, long)]
-        clean: bool,
-    },
-    // Clean project
-    Clean { },
-    // Watch for changes and rebuild
-    Watch { },
-    // Create a new project
-    New {
-        // Project name
-        name: String,
-        
-        // Use default template
-        #[arg(short, long)]
-        default: bool,
-    },
+// Orders mapping keys throughout a YAML value, so values built from HashMaps serialize the same every time
+fn sort_yaml_mappings(value: Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            let mut entries = mapping.into_iter()
+                .map(|(key, value)| (key, sort_yaml_mappings(value)))
+                .collect::<Vec<_>>();
+            entries.sort_by_key(|(key, _)| serde_yaml::to_string(key).unwrap_or_default());
+            Value::Mapping(entries.into_iter().collect())
+        }
+        Value::Sequence(items) => Value::Sequence(items.into_iter().map(sort_yaml_mappings).collect()),
+        other => other,
+    }
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            config_path: None,
-            input_dir: "./".to_string(),
-            output_dir: "output".to_string(),
-            watch: false,
-            variant: None,
-            variants: None,
-            generate_robots_txt: None,
-            generate_sitemap_xml: None,
+// Modification time of a file in milliseconds since the epoch, or 0 when it doesn't exist
+fn file_modified_millis(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// The previous incremental build's cache, if it was made with the same site hash
+fn load_build_cache(output_base: &Path, site_hash: &str) -> BuildCache {
+    let cache_path = output_base.join(BUILD_CACHE_PATH);
+    match fs::read_to_string(&cache_path).map(|content| serde_json::from_str::<BuildCache>(&content)) {
+        Ok(Ok(cache)) if cache.site_hash == site_hash => cache,
+        Ok(Err(e)) => {
+            eprintln!("Warning: could not read {}, rebuilding every page: {}", cache_path.display(), e);
+            BuildCache::default()
         }
+        _ => BuildCache::default(),
     }
 }
 
-impl Config {
-    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
-        let content = fs::read_to_string(path)?;
-        Ok(serde_yaml::from_str(&content)?)
+impl CachedPage {
+    // Whether none of the page's files changed since it was cached and its outputs are still there
+    fn is_fresh(&self, output_base: &Path) -> bool {
+        self.files.iter().all(|(path, modified)| file_modified_millis(Path::new(path)) == *modified)
+            && self.outputs.keys().all(|output| output_base.join(output).is_file())
     }
-    
-    fn relative_to_config_path(&self, path: &PathBuf) -> PathBuf {
-        if let Some(p) = self.config_path.clone() {
-            if path.as_os_str() == "." || path.as_os_str() == "./" {
-                return PathBuf::from(p.as_str());
-            } else {
-                PathBuf::from(p.as_str()).parent().unwrap().into()
+
+    // Whether the page's source or any layout, partial or data file it used is one of paths
+    fn uses_any(&self, paths: &[PathBuf]) -> bool {
+        !paths.is_empty() && self.files.keys().any(|file| paths.contains(&canonicalize_existing_prefix(Path::new(file))))
+    }
+}
+
+fn write_build_cache(verbose: bool, output_base: &Path, cache: BuildCache) -> Result<(), Box<dyn Error>> {
+    let cache_path = output_base.join(BUILD_CACHE_PATH);
+    if verbose {
+        println!("Saving {}", cache_path.display());
+    }
+    fs::write(cache_path, serde_json::to_string_pretty(&cache)?)?;
+    Ok(())
+}
+
+// Compares this build's page hashes with the previous manifest, writes changes.json listing
+// new, changed and removed pages, then saves the new manifest. Without a previous manifest
+// every page is new. Partial (--since) builds keep the old hashes of pages they skipped.
+fn generate_and_write_build_changes(
+    verbose: bool,
+    partial: bool,
+    global_context: &GlobalContext,
+    output_base: &Path,
+    page_hashes: BTreeMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let manifest_path = output_base.join(BUILD_MANIFEST_PATH);
+    let previous = match fs::read_to_string(&manifest_path) {
+        Ok(content) => serde_json::from_str::<BuildManifest>(&content).unwrap_or_else(|e| {
+            eprintln!("Warning: could not read {}, reporting every page as new: {}", manifest_path.display(), e);
+            BuildManifest::default()
+        }),
+        Err(_) => BuildManifest::default(),
+    };
+
+    let page_entry = |path: &String| {
+        let mut entry = serde_json::Map::new();
+        entry.insert("path".to_string(), path.as_str().into());
+        if let Some(url) = global_context.permalink(path) {
+            entry.insert("url".to_string(), url.into());
+        }
+        serde_json::Value::Object(entry)
+    };
+    let mut new_pages = vec![];
+    let mut changed_pages = vec![];
+    for (path, hash) in &page_hashes {
+        match previous.pages.get(path) {
+            None => new_pages.push(page_entry(path)),
+            Some(previous_hash) if previous_hash != hash => changed_pages.push(page_entry(path)),
+            Some(_) => {}
+        }
+    }
+    let removed_pages = if partial {
+        vec![]
+    } else {
+        previous.pages.keys()
+            .filter(|path| !page_hashes.contains_key(*path))
+            .map(page_entry)
+            .collect()
+    };
+
+    if verbose {
+        println!(
+            "Generating {}: {} new, {} changed, {} removed",
+            BUILD_CHANGES_PATH, new_pages.len(), changed_pages.len(), removed_pages.len()
+        );
+    }
+    let changes = serde_json::json!({
+        "new": new_pages,
+        "changed": changed_pages,
+        "removed": removed_pages,
+    });
+    fs::write(output_base.join(BUILD_CHANGES_PATH), serde_json::to_string_pretty(&changes)?)?;
+
+    let mut pages = page_hashes;
+    if partial {
+        for (path, hash) in previous.pages {
+            pages.entry(path).or_insert(hash);
+        }
+    }
+    fs::write(manifest_path, serde_json::to_string_pretty(&BuildManifest { pages })?)?;
+    Ok(())
+}
+
+// ========== QR Codes ==========
+
+// A QR code (error correction level M) as rows of dark/light modules,
+// or None when the data doesn't fit in the largest version
+fn qr_code_modules(data: &[u8]) -> Option<Vec<Vec<bool>>> {
+    let code = qrcode::QrCode::with_error_correction_level(data, qrcode::EcLevel::M).ok()?;
+    let width = code.width();
+    let colors = code.to_colors();
+    Some(colors.chunks(width)
+        .map(|row| row.iter().map(|color| *color == qrcode::Color::Dark).collect())
+        .collect())
+}
+
+// Renders modules as an SVG with a four-module quiet zone, drawn at the given pixel size
+fn qr_code_svg(modules: &[Vec<bool>], size: u32) -> String {
+    let dimension = modules.len() + 8;
+    let mut path = String::new();
+    for (y, row) in modules.iter().enumerate() {
+        for (x, dark) in row.iter().enumerate() {
+            if *dark {
+                path.push_str(&format!("M{},{}h1v1h-1z", x + 4, y + 4));
             }
-        } else {
-            std::env::current_dir().unwrap()
-        }.join(path)
+        }
+    }
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" class=\"qr-code\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {dimension} {dimension}\" shape-rendering=\"crispEdges\"><rect width=\"{dimension}\" height=\"{dimension}\" fill=\"#fff\"/><path d=\"{path}\" fill=\"#000\"/></svg>"
+    )
+}
+
+// ========== Config Schema ==========
+
+// Top-level keys of a config file that Config doesn't have, each with its line and the closest
+// known key when it looks like a typo
+fn unknown_config_keys(content: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let Value::Mapping(mapping) = serde_yaml::from_str::<Value>(content)? else {
+        return Ok(vec![]);
+    };
+    let schema = schemars::schema_for!(Config);
+    let known = schema.schema.object.as_ref()
+        .map(|object| object.properties.keys().map(String::as_str).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut problems = vec![];
+    for key in mapping.keys().filter_map(Value::as_str).filter(|key| !known.contains(key)) {
+        let line = content.lines()
+            .position(|line| line.trim_start_matches(['"', '\'']).strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).trim_start().starts_with(':')))
+            .map_or(String::new(), |i| format!(" on line {}", i + 1));
+        let suggestion = known.iter()
+            .map(|name| (edit_distance(key, name), name))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map_or(String::new(), |(_, name)| format!(", did you mean {}?", name));
+        problems.push(format!("unknown config key {}{}{}", key, line, suggestion));
+    }
+    Ok(problems)
+}
+
+// Levenshtein distance: the fewest single character insertions, deletions and substitutions
+// turning a into b
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// JSON Schema for a config struct, from its schemars derive so it follows the struct's serde attributes
+fn json_schema_for<T: schemars::JsonSchema>(title: &str) -> Result<String, Box<dyn Error>> {
+    let mut schema = schemars::schema_for!(T);
+    schema.schema.metadata().title = Some(title.to_string());
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+// ========== Main Function ==========
+
+// The config file given with --config, else meowdown-config.yaml in the current directory if it exists
+fn config_file_path(cli: &Cli) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    if let Some(config_path) = &cli.config {
+        return Ok(Some(config_path.clone()));
+    }
+    // Try default config locations
+    let default_config_path = std::env::current_dir().unwrap().join("meowdown-config.yaml");
+    Ok(std::fs::exists(&default_config_path)?.then_some(default_config_path))
+}
+
+// The config file (or an empty config) with the global command line settings applied
+fn load_config(cli: &Cli) -> Result<Config, Box<dyn Error>> {
+    let mut config = match config_file_path(cli)? {
+        Some(config_path) => Config::from_file(&config_path)?,
+        None => Config::default(),
+    };
+
+    if cli.strict {
+        config.strict = Some(true);
+    }
+
+    if cli.jobs.is_some() {
+        config.jobs = cli.jobs;
     }
+
+    if cli.profile.is_some() {
+        config.profile = cli.profile;
+    }
+    Ok(config.with_profile_defaults())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let config = load_config(&cli)?;
     
-    fn full_output_path(&self) -> PathBuf {
-        if self.variants.is_some() {
-            panic!("must call build_site_for_each_variant otherwise not sure which to build for");
+    if cli.verbose {
+        println!("Starting with config: {:#?}", config);
+    }
+
+    match &cli.command {
+        Some(Commands::Build { clean, preview, watch, since, force, drafts }) => {
+            let build_config = |config: Config| -> Result<Config, Box<dyn Error>> {
+                let config = if *preview {
+                    Config { preview: Some(true), .. config }
+                } else {
+                    config
+                };
+                let config = Config { force: Some(*force), drafts: Some(*drafts), .. config };
+                Ok(match since {
+                    Some(since) => Config { since: Some(parse_since(since)?), .. config },
+                    None => config,
+                })
+            };
+            let config = build_config(config)?;
+            if *clean {
+                clean_output_dir(&config)?;
+            }
+            // While watching, a failed build is reported and fixed by the next change
+            match build_site_for_each_variant(&config, cli.verbose) {
+                Err(e) if *watch => eprintln!("❌ Build failed: {}", e),
+                result => result?,
+            }
+            if *watch {
+                // Only the first build is forced; rebuilds while watching use the cache
+                let reloader = ConfigReloader {
+                    file: config_file_path(&cli)?,
+                    load: &|| Ok(Config { force: None, .. build_config(load_config(&cli)?)? }),
+                };
+                watch_and_rebuild(&Config { force: None, .. config }, &reloader, None, cli.verbose)?;
+            }
+        }
+        Some(Commands::Clean { }) => {
+            clean_output_dir(&config)?;
+        }
+        Some(Commands::Watch { drafts }) => {
+            let reloader = ConfigReloader {
+                file: config_file_path(&cli)?,
+                load: &|| Ok(Config { drafts: Some(*drafts), .. load_config(&cli)? }),
+            };
+            watch_and_rebuild(&Config { drafts: Some(*drafts), .. config }, &reloader, None, cli.verbose)?;
+        }
+        Some(Commands::New { name, default, template, index_filename }) => {
+            match template.as_deref() {
+                None => create_new_project(name, *default, index_filename.as_deref(), cli.verbose)?,
+                Some("default") => create_new_project(name, true, index_filename.as_deref(), cli.verbose)?,
+                Some("blank") => create_new_project(name, false, index_filename.as_deref(), cli.verbose)?,
+                Some(template) => create_project_from_template(name, template, cli.verbose)?,
+            }
+        }
+        Some(Commands::Serve { port, host, watch, no_live_reload }) => {
+            match build_site_for_each_variant(&config, cli.verbose) {
+                Err(e) if *watch => eprintln!("❌ Build failed: {}", e),
+                result => result?,
+            }
+            let live_reload = (*watch && !*no_live_reload).then(LiveReload::default);
+            let reloader = ConfigReloader {
+                file: config_file_path(&cli)?,
+                load: &|| load_config(&cli),
+            };
+            serve_site(&config, host, *port, watch.then_some(&reloader), live_reload, cli.verbose)?;
+        }
+        Some(Commands::Check { }) => {
+            // Every problem fails the check, as under strict
+            Config { strict: Some(true), .. config }.validate()?;
+            println!("Config OK");
+        }
+        Some(Commands::Config { variant, format }) => {
+            print_effective_config(&config, variant.as_deref(), format)?;
+        }
+        Some(Commands::Schema { file }) => {
+            let schema = match file.as_str() {
+                "config" => json_schema_for::<Config>("meowdown-config.yaml")?,
+                "robots" => json_schema_for::<RobotsConfig>("data/robots_config.yaml")?,
+                other => return Err(format!("unknown schema {}, expected config or robots", other).into()),
+            };
+            println!("{}", schema);
         }
+        None => {
+            // Default to build command
+            build_site_for_each_variant(&config, cli.verbose)?;
+        }
+    }
+    Ok(())
+}
 
-        let p = if let Some(variant) = &self.variant {
-            self.output_dir.replace("{{variant}}", variant)
-        } else {
-            self.output_dir.clone()
-        };
+// The config file watching reloads the config from when it changes, and how: loading it again with
+// the command line settings applied
+pub struct ConfigReloader<'a> {
+    file: Option<PathBuf>,
+    load: &'a dyn Fn() -> Result<Config, Box<dyn Error>>,
+}
 
-        if p.is_empty() || p == "." || p == "./" {
-            self.config_path.clone().map(|x| PathBuf::from(x)).or(std::env::current_dir().ok()).unwrap()
-        } else {
-            self.relative_to_config_path(&PathBuf::from(&p))
+impl ConfigReloader<'_> {
+    // Loads the config again into config, leaving it as it was when the file no longer loads
+    fn reload(&self, config: &mut Config) -> Result<(), Box<dyn Error>> {
+        *config = (self.load)()?;
+        Ok(())
+    }
+}
+
+// Rebuilds on changes; after each successful rebuild, pages open through serve --watch are
+// told to reload
+pub fn watch_and_rebuild(
+    config: &Config,
+    reloader: &ConfigReloader,
+    live_reload: Option<&LiveReload>,
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔭 Watching for changes... (Press Ctrl+C to stop)");
+    let mut config = config.clone();
+
+    // Create channel for file change events
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher: RecommendedWatcher = Watcher::new(
+        tx, 
+        notify::Config::default()
+            .with_poll_interval(Duration::from_secs(1)) // Debounce time
+    )?;
+
+    // Watch relevant directories
+    let mut watched = watched_dirs(&config);
+    for dir in &watched {
+        watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+        if verbose {
+            println!("👀 Watching: {}", dir.display());
         }
     }
-    
-    pub fn full_input_path(&self) -> PathBuf {
-        let p = if let Some(variant) = &self.variant {
-            self.input_dir.replace("{{variant}}", variant)
-        } else {
-            self.input_dir.clone()
-        };
 
-        if p.is_empty() || p == "." || p == "./" {
-            self.config_path.clone().map(|x| PathBuf::from(x)).or(std::env::current_dir().ok()).unwrap()
-        } else {
-            self.relative_to_config_path(&PathBuf::from(&p))
+    // The config file's directory is watched rather than the file, which editors often replace
+    // when saving
+    let config_file = reloader.file.as_ref().and_then(|file| file.canonicalize().ok());
+    let config_dir = config_file.as_ref().and_then(|file| file.parent()).map(Path::to_path_buf);
+    let watch_config_dir = |watcher: &mut RecommendedWatcher, watched: &[PathBuf]| -> notify::Result<()> {
+        match &config_dir {
+            Some(dir) if !watched.iter().any(|w| dir.starts_with(w)) => watcher.watch(dir, notify::RecursiveMode::NonRecursive),
+            _ => Ok(()),
+        }
+    };
+    watch_config_dir(&mut watcher, &watched)?;
+    if verbose {
+        if let Some(config_file) = &config_file {
+            println!("👀 Watching: {}", config_file.display());
         }
     }
-}
\ No newline at end of file
+
+    // Track last build time to avoid rapid rebuilds
+    let mut last_build = std::time::Instant::now();
+    let min_rebuild_interval = Duration::from_secs(2);
+
+    loop {
+        match rx.recv() {
+            // Filter relevant changes
+            Ok(Ok(notify::Event { kind: notify::EventKind::Create(_) | notify::EventKind::Modify(_) | notify::EventKind::Remove(_), paths, .. }))
+                if should_trigger_rebuild(&paths, &config) && last_build.elapsed() > min_rebuild_interval => {
+                if verbose {
+                    println!("\n📡 Change detected in: {:?}",
+                        paths.iter().map(|p| p.display()).collect::<Vec<_>>()
+                    );
+                }
+
+                if config_file.as_ref().is_some_and(|file| paths.contains(file)) {
+                    match reloader.reload(&mut config) {
+                        Ok(()) => {
+                            println!("🔄 Reloaded {}", config_file.as_ref().unwrap().display());
+
+                            // The input, assets, data or template directories may have moved
+                            let dirs = watched_dirs(&config);
+                            for dir in watched.iter().filter(|dir| !dirs.contains(dir)) {
+                                watcher.unwatch(dir)?;
+                            }
+                            for dir in dirs.iter().filter(|dir| !watched.contains(dir)) {
+                                watcher.watch(dir, notify::RecursiveMode::Recursive)?;
+                                if verbose {
+                                    println!("👀 Watching: {}", dir.display());
+                                }
+                            }
+                            watched = dirs;
+                            watch_config_dir(&mut watcher, &watched)?;
+                        }
+                        Err(e) => {
+                            println!("❌ Could not reload config, keeping the last one that loaded: {}", e);
+                            continue;
+                        }
+                    }
+                }
+
+                let changed_paths = paths.iter().map(|path| canonicalize_existing_prefix(path)).collect();
+                match build_site_for_each_variant(&Config { changed_paths: Some(changed_paths), .. config.clone() }, verbose) {
+                    Ok(_) => {
+                        println!("✅ Rebuild successful!");
+                        if let Some(live_reload) = live_reload {
+                            live_reload.notify();
+                        }
+                        last_build = std::time::Instant::now();
+                    }
+                    Err(e) => {
+                        println!("❌ Build failed: {}", e);
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("⚠️ Watch error: {}", e),
+            _ => {}
+        }
+    }
+}
+
+// The existing directories a build reads: input, assets, data and templates, leaving out any
+// inside another since watches are recursive
+fn watched_dirs(config: &Config) -> Vec<PathBuf> {
+    let mut dirs = vec![
+        config.full_input_path(),
+        config.relative_to_config_path(&PathBuf::from("assets")),
+        config.relative_to_config_path(&PathBuf::from("data")),
+    ];
+    dirs.extend(config.template_search_dirs());
+
+    let mut watched: Vec<PathBuf> = vec![];
+    for dir in dirs.iter().filter_map(|dir| dir.canonicalize().ok()) {
+        if watched.iter().any(|w| dir.starts_with(w)) {
+            continue;
+        }
+        watched.retain(|w| !w.starts_with(&dir));
+        watched.push(dir);
+    }
+    watched
+}
+
+fn should_trigger_rebuild(paths: &[PathBuf], config: &Config) -> bool {
+    let markdown_extensions = config.markdown_extensions();
+    // The build's own output may sit inside a watched directory
+    let output_dirs = config.variant_configs().iter()
+        .map(|cfg| canonicalize_existing_prefix(&cfg.full_output_path()))
+        .collect::<Vec<_>>();
+    paths.iter().any(|p| {
+        if output_dirs.iter().any(|dir| canonicalize_existing_prefix(p).starts_with(dir)) {
+            return false;
+        }
+        // Layouts are name.tpl.html, whose extension is just html
+        if p.file_name().is_some_and(|name| name.to_string_lossy().ends_with(".tpl.html")) {
+            return true;
+        }
+        // Only trigger for these file types
+        match p.extension().and_then(|e| e.to_str()) {
+            Some("html" | "css" | "scss" | "sass" | "js" | "svg" | "txt" | "yml" | "yaml" | "json" | "csv") => true,
+            Some(ext) => markdown_extensions.iter().any(|e| e == ext),
+            _ => false
+        }
+    })
+}
+
+// Path the live reload script listens on for reload events
+const LIVE_RELOAD_PATH: &str = "/__meowdown/live-reload";
+
+// Injected into served pages by serve --watch. EventSource reconnects on its own when the
+// server restarts, and the page reloads once it is back to pick up the new build
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var source = new EventSource("/__meowdown/live-reload");
+  var lostConnection = false;
+  source.addEventListener("reload", function () { location.reload(); });
+  source.onerror = function () { lostConnection = true; };
+  source.onopen = function () { if (lostConnection) { location.reload(); } };
+})();
+</script>
+"#;
+
+// Open live reload connections, shared by the server threads and the watcher
+#[derive(Clone, Default)]
+pub struct LiveReload {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl LiveReload {
+    fn add_client(&self, stream: TcpStream) {
+        self.clients.lock().unwrap().push(stream);
+    }
+
+    // Sends a reload event to every open page, dropping connections that have gone away
+    fn notify(&self) {
+        self.clients.lock().unwrap()
+            .retain_mut(|stream| stream.write_all(b"event: reload\ndata: reload\n\n").and_then(|_| stream.flush()).is_ok());
+    }
+}
+
+// Serves the built output over HTTP until the process is stopped. With variants, the first
+// variant's output is served. With watch, rebuilds on changes and, given live_reload, injects
+// a script into served pages that reloads them after each rebuild
+pub fn serve_site(config: &Config, host: &str, port: u16, watch: Option<&ConfigReloader>, live_reload: Option<LiveReload>, verbose: bool) -> Result<(), Box<dyn Error>> {
+    let serve_config = match config.variants.as_ref().and_then(|variants| variants.first()) {
+        Some(variant) => Config { variant: Some(variant.clone()), variants: None, .. config.clone() },
+        None => config.clone(),
+    };
+    let root = serve_config.full_output_path();
+    let is_empty = fs::read_dir(&root).map_or(true, |mut entries| entries.next().is_none());
+    if is_empty {
+        return Err(format!("output directory {} is empty, nothing to serve", root.display()).into());
+    }
+
+    let listener = TcpListener::bind((host, port))
+        .map_err(|e| format!("could not listen on {}:{}: {}", host, port, e))?;
+    println!("🌐 Serving {} at http://{}:{}/ (Press Ctrl+C to stop)", root.display(), host, port);
+
+    let index_file = serve_config.index_file_name();
+    let server_live_reload = live_reload.clone();
+    let accept_connections = move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Warning: failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let root = root.clone();
+            let index_file = index_file.clone();
+            let live_reload = server_live_reload.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = handle_http_request(stream, &root, &index_file, live_reload.as_ref(), verbose) {
+                    if verbose {
+                        eprintln!("Warning: request failed: {}", e);
+                    }
+                }
+            });
+        }
+    };
+
+    if let Some(reloader) = watch {
+        std::thread::spawn(accept_connections);
+        watch_and_rebuild(config, reloader, live_reload.as_ref(), verbose)
+    } else {
+        accept_connections();
+        Ok(())
+    }
+}
+
+// Answers one GET or HEAD request with a file under root: directories get their index file and
+// extensionless paths fall back to .html, so clean URLs work. Anything else is a 404. With
+// live_reload, HTML pages get the live reload script and its event stream is kept open
+fn handle_http_request(mut stream: TcpStream, root: &Path, index_file: &str, live_reload: Option<&LiveReload>, verbose: bool) -> Result<(), Box<dyn Error>> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or("/"));
+    if verbose {
+        println!("{} {}", method, target);
+    }
+    if method != "GET" && method != "HEAD" {
+        return write_http_response(&mut stream, "405 Method Not Allowed", "text/plain; charset=utf-8", b"Method Not Allowed", true);
+    }
+
+    let path = url_decode(target.split(['?', '#']).next().unwrap_or_default());
+    if let (Some(live_reload), LIVE_RELOAD_PATH) = (live_reload, path.as_str()) {
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\nretry: 1000\n\n")?;
+        stream.flush()?;
+        live_reload.add_client(stream);
+        return Ok(());
+    }
+    let relative = PathBuf::from(path.trim_start_matches('/'));
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return write_http_response(&mut stream, "404 Not Found", "text/plain; charset=utf-8", b"Not Found", method == "GET");
+    }
+
+    let mut file_path = root.join(&relative);
+    if file_path.is_dir() {
+        file_path = file_path.join(index_file);
+    } else if !file_path.exists() && file_path.extension().is_none() {
+        file_path = file_path.with_extension("html");
+    }
+    match fs::read(&file_path) {
+        Ok(body) => {
+            let content_type = mime_type_for_path(&file_path);
+            let body = match live_reload {
+                Some(_) if content_type.starts_with("text/html") => {
+                    insert_before_closing_tag(&String::from_utf8_lossy(&body), "body", LIVE_RELOAD_SCRIPT).into_bytes()
+                }
+                _ => body,
+            };
+            write_http_response(&mut stream, "200 OK", content_type, &body, method == "GET")
+        }
+        Err(_) => write_http_response(&mut stream, "404 Not Found", "text/plain; charset=utf-8", b"Not Found", method == "GET"),
+    }
+}
+
+fn write_http_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8], include_body: bool) -> Result<(), Box<dyn Error>> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    )?;
+    if include_body {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+// Content-Type for files served by the dev server, keyed on extension
+fn mime_type_for_path(path: &Path) -> &'static str {
+    let ext = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("yml" | "yaml") => "application/yaml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn create_new_project(
+    name: &str,
+    use_default_template: bool,
+    index_filename: Option<&str>,
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let project_dir = std::env::current_dir().unwrap().join(name);
+    println!("creating new project at {:?}", project_dir);
+    
+    // Create project directory structure
+    create_dir(&project_dir, verbose)?;
+    create_dir(&project_dir.join("assets"), verbose)?;
+    create_dir(&project_dir.join("data"), verbose)?;
+    create_dir(&project_dir.join("templates"), verbose)?;
+
+    // Create default files
+    create_file(
+        &project_dir.join("data/site.yaml"),
+        include_str!("../_default-data/data/default_site.yaml"),
+        verbose,
+    )?;
+
+    let mut config_yaml = include_str!("../_default-data/default_meowdown-config.yaml").to_string();
+    if let Some(index_filename) = index_filename {
+        config_yaml = format!("{}\nindex_filename: \"{}\"\n", config_yaml.trim_end(), index_filename);
+    }
+    create_file(
+        &project_dir.join("meowdown-config.yaml"),
+        &config_yaml,
+        verbose,
+    )?;
+
+    create_file(
+        &project_dir.join(format!("{}.md", index_filename.unwrap_or("index"))),
+        include_str!("../_default-data/default_index.md"),
+        verbose,
+    )?;
+
+    if use_default_template {
+        create_file(
+            &project_dir.join("templates/default.tpl.html"),
+            include_str!("../_default-data/templates/default_layout.tpl.html"),
+            verbose,
+        )?;
+        
+        create_file(
+            &project_dir.join("assets/style.css"),
+            include_str!("../_default-data/assets/default_style.css"),
+            verbose,
+        )?;
+
+        create_file(
+            &project_dir.join("assets/reading-progress.js"),
+            include_str!("../_default-data/assets/default_reading_progress.js"),
+            verbose,
+        )?;
+    }
+
+    println!("✨ Created new project '{}' successfully!", name);
+
+    if !use_default_template {
+        println!("⚠️ No templates were included. Add your own in templates/");
+    }
+
+    Ok(())
+}
+
+// Copies a template directory, or clones a git repository, into a new project directory
+pub fn create_project_from_template(
+    name: &str,
+    template: &str,
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let project_dir = std::env::current_dir().unwrap().join(name);
+    if project_dir.exists() {
+        return Err(format!("{} already exists", project_dir.display()).into());
+    }
+    println!("creating new project at {:?} from {}", project_dir, template);
+
+    if Path::new(template).is_dir() {
+        copy_assets(template, project_dir.to_str().unwrap(), false, verbose)?;
+    } else if has_protocol(template) || template.starts_with("git@") || template.ends_with(".git") {
+        let status = Command::new("git")
+            .args(["clone", "--depth", "1", template])
+            .arg(&project_dir)
+            .status()
+            .map_err(|e| format!("could not run git to clone {}: {}", template, e))?;
+        if !status.success() {
+            return Err(format!("git clone of {} failed", template).into());
+        }
+    } else {
+        return Err(format!(
+            "template {} is not a directory, a git URL or a built-in template (default, blank)", template
+        ).into());
+    }
+
+    // The new project starts its own history
+    let git_dir = project_dir.join(".git");
+    if git_dir.exists() {
+        fs::remove_dir_all(git_dir)?;
+    }
+
+    if !project_dir.join("meowdown-config.yaml").exists() {
+        println!("⚠️ The template has no meowdown-config.yaml, add one before building");
+    }
+    println!("✨ Created new project '{}' successfully!", name);
+    Ok(())
+}
+
+// Helper function to create directory with verbose output
+fn create_dir(path: &Path, verbose: bool) -> std::io::Result<()> {
+    if verbose {
+        println!("Creating directory: {}", path.display());
+    }
+    fs::create_dir_all(path)
+}
+
+// Helper function to create file with verbose output
+fn create_file(path: &Path, content: &str, verbose: bool) -> std::io::Result<()> {
+    if verbose {
+        println!("Creating file: {}", path.display());
+    }
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+// Prints the resolved Config and site strings, once per variant being built
+fn print_effective_config(config: &Config, variant: Option<&str>, format: &str) -> Result<(), Box<dyn Error>> {
+    let configs = if let Some(variant) = variant {
+        vec![Config { variant: Some(variant.to_string()), variants: None, .. config.clone() }]
+    } else if let Some(variants) = &config.variants {
+        variants.iter()
+            .map(|v| Config { variant: Some(v.clone()), variants: None, .. config.clone() })
+            .collect()
+    } else {
+        vec![config.clone()]
+    };
+
+    for cfg in configs {
+        println!("{}", effective_config(&cfg, format)?);
+    }
+    Ok(())
+}
+
+// One variant's resolved Config and site strings as a YAML document or as JSON
+fn effective_config(cfg: &Config, format: &str) -> Result<String, Box<dyn Error>> {
+    let global_context = GlobalContext::new_with_defaults(cfg.clone())?;
+    let mut site = global_context.site_strings.iter().collect::<Vec<_>>();
+    site.sort();
+
+    let mut effective = serde_yaml::Mapping::new();
+    effective.insert("config".into(), serde_yaml::to_value(cfg)?);
+    effective.insert("site".into(), Value::Mapping(
+        site.into_iter().map(|(k, v)| (k.as_str().into(), v.as_str().into())).collect()
+    ));
+    let effective = Value::Mapping(effective);
+
+    match format {
+        "yaml" => Ok(format!("---\n{}", serde_yaml::to_string(&effective)?)),
+        "json" => Ok(serde_json::to_string_pretty(&effective)?),
+        _ => Err(format!("unknown format {}, expected yaml or json", format).into()),
+    }
+}
+
+fn clean_output_dir(config: &Config) -> Result<(), Box<dyn Error>> {
+    let output = config.full_output_path();
+    fs::remove_dir_all(output)
+        .map_err(|e| e.into())
+}
+
+fn build_site_for_each_variant(config: &Config, verbose: bool) -> Result<(), Box<dyn Error>> {
+    config.check_variants()?;
+    if config.variant.is_some() {
+        build_site(config, verbose)
+    } else if let Some(variants) = &config.variants {
+        let variant_configs = config.variant_configs();
+        let mut jobs = config.jobs.filter(|&jobs| jobs > 0)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+            .min(variants.len());
+        // Variants writing to the same directory would overwrite each other's files mid-build
+        let output_dirs = variant_configs.iter()
+            .map(|cfg_variant| canonicalize_existing_prefix(&cfg_variant.full_output_path()))
+            .collect::<BTreeSet<_>>();
+        if jobs > 1 && output_dirs.len() < variant_configs.len() {
+            eprintln!("Warning: variants share an output directory (add {{{{variant}}}} to output_dir to separate them), building them one at a time");
+            jobs = 1;
+        }
+        if jobs <= 1 {
+            for cfg_variant in &variant_configs {
+                build_site(cfg_variant, verbose)?;
+            }
+            return Ok(());
+        }
+
+        // Each worker takes the next unbuilt variant until none are left
+        let next_variant = AtomicUsize::new(0);
+        let errors = std::thread::scope(|scope| {
+            let workers = (0..jobs)
+                .map(|_| scope.spawn(|| {
+                    let mut errors = vec![];
+                    while let Some(cfg_variant) = variant_configs.get(next_variant.fetch_add(1, Ordering::Relaxed)) {
+                        if let Err(e) = build_site(cfg_variant, verbose) {
+                            errors.push(format!("variant {}: {}", cfg_variant.variant.as_deref().unwrap_or_default(), e));
+                        }
+                    }
+                    errors
+                }))
+                .collect::<Vec<_>>();
+            workers.into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|_| vec!["a variant build panicked".to_string()]))
+                .collect::<Vec<_>>()
+        });
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n").into())
+        }
+    } else {
+        build_site(config, verbose)
+    }
+}
+
+fn build_site(config: &Config, verbose: bool) -> Result<(), Box<dyn Error>> {
+    config.validate()?;
+    let output_base = config.full_output_path();
+    if verbose {
+        println!("outputting to {}", output_base.to_str().unwrap());
+    }
+
+    let mut global_context = GlobalContext::new_with_defaults(config.clone())?;
+    create_dir(&output_base, verbose)?;
+    let assets_dir = config.relative_to_config_path(&PathBuf::from("assets"));
+    if config.fingerprint_assets.unwrap_or(false) {
+        (global_context.asset_manifest, global_context.asset_sources) = asset_fingerprints(&assets_dir, config.compile_scss.unwrap_or(false))?;
+    }
+    
+    let output_dir = canonicalize_existing_prefix(&output_base);
+    let mut page_paths = match &config.content_dir {
+        Some(_) => get_md_files_recursive(&config.full_content_path(), false, &config.markdown_extensions(), &output_dir),
+        None => get_md_files_recursive(&config.full_input_path(), true, &config.markdown_extensions(), &output_dir)
+            .into_iter()
+            .filter(|p| !p.contains("/assets/") && !p.contains("assets/"))
+            .collect::<Vec<_>>(),
+    };
+    // read_dir order differs between platforms; sort so output and tie-breaks are reproducible
+    page_paths.sort();
+    global_context.collect_pages(&page_paths)?;
+
+    // --since only rebuilds recently modified content; listings above still see every page
+    let page_paths = match config.since {
+        Some(since) => page_paths.into_iter()
+            .filter(|p| fs::metadata(p)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| DateTime::<Utc>::from(modified) >= since))
+            .collect(),
+        None => page_paths,
+    };
+
+    global_context.head_snippet = config.load_snippet(&config.head_snippet, &config.head_snippet_file)?;
+    global_context.body_snippet = config.load_snippet(&config.body_snippet, &config.body_snippet_file)?;
+    
+    let default_changefreq = match &config.default_changefreq {
+        Some(c) => c.trim().to_lowercase().parse::<ChangeFrequency>()
+            .map_err(|_| format!("invalid default_changefreq {}, expected one of always, hourly, daily, weekly, monthly, yearly, never", c))?,
+        None => ChangeFrequency::Monthly,
+    };
+    
+    // Incremental builds reuse pages whose source, layouts and data are unchanged since the last build,
+    // as do watch rebuilds, which only redo the pages using a file that changed
+    let incremental = config.incremental.unwrap_or(false) || config.changed_paths.is_some();
+    let changed_paths = config.changed_paths.as_deref().unwrap_or_default();
+    let site_hash = global_context.site_hash();
+    let previous_cache = if incremental && !config.force.unwrap_or(false) {
+        load_build_cache(&output_base, &site_hash)
+    } else {
+        BuildCache::default()
+    };
+    let mut build_cache = BuildCache { site_hash, pages: BTreeMap::new() };
+
+    // Build and render all pages
+    let mut output_html_paths = vec![];
+    let mut sitemap_xml_nodes = vec![];
+    let mut unresolved_count = 0;
+    let mut page_hashes = BTreeMap::new();
+    // Drafts are only written when asked for; preview builds always include them
+    let include_drafts = config.drafts.unwrap_or(false) || config.preview.unwrap_or(false);
+    for path in page_paths {
+        if let Some(draft) = global_context.pages.iter().find(|p| p.source_path == path && is_draft(&p.front_matter)).filter(|_| !include_drafts) {
+            if verbose {
+                println!("skipped (draft) {}", path);
+            }
+            // A build with --drafts may have written it before
+            if draft.output_path.is_file() {
+                fs::remove_file(&draft.output_path)?;
+                if verbose {
+                    println!("removed {}", draft.output_path.display());
+                }
+            }
+            continue;
+        }
+
+        if let Some(cached) = previous_cache.pages.get(&path).filter(|cached| cached.is_fresh(&output_base) && !cached.uses_any(changed_paths)) {
+            if verbose {
+                println!("skipped (unchanged) {}", path);
+            }
+            if let Some(info) = global_context.pages.iter().find(|p| p.source_path == path) {
+                output_html_paths.push(PathBuf::from(&info.output_path.to_str().unwrap()[output_base.to_str().unwrap().len()..]));
+                sitemap_xml_nodes.extend(sitemap_node_for_page(config, &global_context, &path, &info.output_path, &info.front_matter, &default_changefreq));
+            }
+            page_hashes.extend(cached.outputs.clone());
+            build_cache.pages.insert(path, cached.clone());
+            continue;
+        }
+
+        global_context.page_dependencies.borrow_mut().clear();
+        let mut page_outputs = BTreeMap::new();
+        let page = global_context.build_page(&path)?;
+        
+        if verbose {
+            // Print the tree structure
+            page.print_tree(0);
+        }
+        
+        if let TemplateNode::Page { path, output_path, front_matter, .. } = &*page {
+            // A paginated listing is written once per page, each with its slice of the entries
+            let first_page = global_context.site_relative_path(output_path);
+            for (listing_page, pagination) in global_context.paginated_pages(&page) {
+                let TemplateNode::Page { output_path, .. } = &*listing_page else {
+                    continue;
+                };
+                let ctx = global_context.page_render_context(path, output_path, front_matter);
+                if let Some((page_number, total_pages)) = pagination {
+                    global_context.add_pagination_context(&ctx, &first_page, page_number, total_pages);
+                }
+
+                create_dir(output_path.parent().unwrap(), verbose)?;
+
+                if verbose {
+                    println!("writing html to {}", output_path.to_str().unwrap());
+                }
+                let relative_path = PathBuf::from(&output_path.to_str().unwrap()[output_base.to_str().unwrap().len()..]);
+                output_html_paths.push(relative_path.clone());
+
+                sitemap_xml_nodes.extend(sitemap_node_for_page(config, &global_context, path, output_path, front_matter, &default_changefreq));
+
+                let html = listing_page.render(ctx.clone(), &mut global_context);
+                global_context.take_render_errors()?;
+                let html = global_context.apply_post_render_transforms(html, ctx);
+
+                if config.strict_variables.unwrap_or(false) {
+                    for placeholder in find_unresolved_placeholders(&html) {
+                        eprintln!("Warning: unresolved template variable {} in {}", placeholder, path);
+                        unresolved_count += 1;
+                    }
+                }
+
+                // After the check above, so escaped {{ }} in the page aren't reported as unresolved
+                let html = restore_raw_text(html);
+
+                if config.check_charset.unwrap_or(false) && !declares_charset(&html) {
+                    eprintln!("Warning: {} has no <meta charset> declaration, add <meta charset=\"utf-8\"> to its layout", path);
+                }
+
+                // Output is always UTF-8; the BOM just makes that explicit for picky consumers
+                let html = if config.utf8_bom.unwrap_or(false) {
+                    format!("\u{FEFF}{}", html)
+                } else {
+                    html
+                };
+                page_outputs.insert(global_context.site_relative_path(output_path), content_hash(html.as_bytes()));
+                fs::write(output_path, html)?;
+            }
+
+            // Other formats from the outputs front matter stay out of the sitemap and skip HTML transforms
+            for output in global_context.page_outputs(&page) {
+                let TemplateNode::Page { output_path, .. } = &*output else {
+                    continue;
+                };
+                if verbose {
+                    println!("writing {}", output_path.display());
+                }
+                let ctx = global_context.page_render_context(path, output_path, front_matter);
+                let content = restore_raw_text(output.render(ctx, &mut global_context));
+                global_context.take_render_errors()?;
+                page_outputs.insert(global_context.site_relative_path(output_path), content_hash(content.as_bytes()));
+                fs::write(output_path, content)?;
+            }
+        } else {
+            panic!("could not build page {}", path);
+        }
+
+        page_hashes.extend(page_outputs.clone());
+        if incremental {
+            let mut files = BTreeMap::new();
+            files.insert(path.clone(), file_modified_millis(Path::new(&path)));
+            for dependency in global_context.page_dependencies.borrow().iter() {
+                files.insert(dependency.to_string_lossy().into_owned(), file_modified_millis(dependency));
+            }
+            build_cache.pages.insert(path, CachedPage { files, outputs: page_outputs });
+        }
+    }
+
+    // A --since build only saw some pages, so its cache would forget the rest
+    if incremental && config.since.is_none() {
+        write_build_cache(verbose, &output_base, build_cache)?;
+    }
+    
+    if unresolved_count > 0 && config.strict.unwrap_or(false) {
+        return Err(format!("{} unresolved template variable(s) found (strict mode)", unresolved_count).into());
+    }
+
+    if config.changes_json.unwrap_or(false) {
+        generate_and_write_build_changes(verbose, config.since.is_some(), &global_context, &output_base, page_hashes)?;
+    }
+
+    if let Some(redirects) = global_context.load_redirects()? {
+        generate_and_write_redirects(verbose, &global_context, &output_base, &output_html_paths, &redirects)?;
+    }
+    
+    copy_assets(
+        assets_dir.to_str().unwrap(), 
+        output_base.join("assets").to_str().unwrap(), 
+        config.compile_scss.unwrap_or(false),
+        verbose
+    )?;
+    if config.fingerprint_assets.unwrap_or(false) {
+        write_fingerprinted_assets(verbose, &output_base, &global_context.asset_manifest)?;
+    }
+
+    if config.check_links.unwrap_or(false) {
+        let broken_links = global_context.check_links();
+        if broken_links > 0 && config.strict.unwrap_or(false) {
+            return Err(format!("{} broken link(s) found (strict mode)", broken_links).into());
+        }
+    }
+
+    match global_context.load_robots_config()? {
+        // A partial build doesn't know every page, so keep the existing sitemap and robots.txt
+        _ if config.since.is_some() => {
+            if verbose {
+                println!("Not generating sitemap.xml or robots.txt for a --since build");
+            }
+        },
+        robots_config if config.generate_robots_txt.unwrap_or(false) => {
+            let robots_config = robots_config
+                .unwrap_or_else(|| RobotsConfig::allow_all(global_context.relative_url(&config.sitemap_path())));
+            generate_and_write_sitemap_xml(verbose, config.pretty_xml.unwrap_or(false), &output_base, &config.sitemap_path(), sitemap_xml_nodes)?;
+            generate_and_write_robots_txt(verbose, &output_base, &config.robots_path(), output_html_paths, robots_config)?;
+        },
+        _ if config.generate_sitemap_xml.unwrap_or(false) => {
+            generate_and_write_sitemap_xml(verbose, config.pretty_xml.unwrap_or(false), &output_base, &config.sitemap_path(), sitemap_xml_nodes)?;
+        },
+        _ => {
+            if verbose {
+                println!("Not generating sitemap.xml or robots.txt");
+            }
+        }
+    }
+
+    if config.generate_humans_txt.unwrap_or(false) {
+        generate_and_write_humans_txt(verbose, config, &output_base)?;
+    }
+
+    if config.generate_security_txt.unwrap_or(false) {
+        let security_txt = config.security_txt.as_ref()
+            .ok_or("generate_security_txt is set but security_txt is missing from the config")?;
+        generate_and_write_security_txt(verbose, security_txt, &output_base)?;
+    }
+
+    if config.generate_opensearch.unwrap_or(false) {
+        let opensearch = config.opensearch.as_ref()
+            .ok_or("generate_opensearch is set but opensearch is missing from the config")?;
+        generate_and_write_opensearch_xml(verbose, config.pretty_xml.unwrap_or(false), opensearch, &global_context, &output_base)?;
+    }
+
+    if config.generate_rss.unwrap_or(false) {
+        generate_and_write_rss_xml(verbose, config.pretty_xml.unwrap_or(false), &global_context, &output_base)?;
+    }
+
+    if let Some(variant) = &config.variant {
+        println!("Site generation for variant {} complete!", variant);
+    } else {
+        println!("Site generation complete!");
+    }
+    Ok(())
+}
+
+// Sitemap entry for a page, unless it is unpublished in a preview build or marked noindex
+fn sitemap_node_for_page(
+    config: &Config,
+    global_context: &GlobalContext,
+    path: &str,
+    output_path: &Path,
+    front_matter: &FrontMatter,
+    default_changefreq: &ChangeFrequency,
+) -> Option<SitemapXmlNode> {
+    // Drafts stay out of the sitemap even when --drafts writes them, as do the future-dated pages
+    // preview builds include
+    let unpublished = is_draft(front_matter) || (config.preview.unwrap_or(false) && is_future_dated(front_matter));
+
+    let noindex = front_matter.get("robots").and_then(FrontMatterValue::as_str)
+        .or(config.default_robots.as_deref())
+        .is_some_and(|robots| robots.split(',').any(|d| d.trim().eq_ignore_ascii_case("noindex")));
+
+    if unpublished || noindex {
+        return None;
+    }
+    // Checkouts reset file times, so the last commit date is more telling when it's available
+    let lastmod = config.sitemap_lastmod_from_git.unwrap_or(false)
+        .then(|| global_context.git_commit_date(Path::new(path)))
+        .flatten()
+        .or_else(|| fs::File::open(path)
+            .map(|f| f.metadata().map(|t| t.modified().ok()).ok()).ok()
+            .flatten().flatten()
+            .map(DateTime::<Utc>::from));
+    let changefreq = front_matter.get("changefreq").and_then(FrontMatterValue::as_str)
+        .and_then(|c| parse_changefreq(c, path))
+        .unwrap_or_else(|| default_changefreq.clone());
+    let priority = front_matter.get("priority").and_then(FrontMatterValue::as_str)
+        .and_then(|p| p.trim().parse::<f32>().ok())
+        .or(config.default_priority)
+        .map(|p| p.clamp(0.0, 1.0));
+    Some(SitemapXmlNode {
+        changefreq: Some(changefreq),
+        loc: global_context.page_url(output_path),
+        lastmod,
+        priority,
+        alternates: vec![],
+    })
+}
+
+#[derive(Parser)]
+#[command(name = "MeowDown")]
+#[command(version = "1.0")]
+#[command(about = "A static site generator", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    
+    // Path to config file (optional)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    
+    // Verbose output
+    #[arg(short, long)]
+    verbose: bool,
+
+    // Turn warnings (e.g. unresolved template variables) into build errors
+    #[arg(long)]
+    strict: bool,
+
+    // Settings preset (dev or prod), overridden by explicit config values
+    #[arg(long)]
+    profile: Option<Profile>,
+
+    // Maximum number of variants built at once (default: CPU count, 1 builds one at a time)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+#[derive(clap::Subcommand)]
+enum Commands {
+    // Build the site
+    Build {    
+        // Clean output directory before building
+        #[arg(short, long)]
+        clean: bool,
+
+        // Include drafts and future-dated pages, writing to the preview output directory
+        #[arg(short, long)]
+        preview: bool,
+
+        // Keep watching for changes after the initial build
+        #[arg(short, long)]
+        watch: bool,
+
+        // Only rebuild markdown modified within a window (e.g. 10m, 2h, 1d) or since a date
+        #[arg(long)]
+        since: Option<String>,
+
+        // Rebuild every page, ignoring the incremental build cache
+        #[arg(long, visible_alias = "no-cache")]
+        force: bool,
+
+        // Also write pages with draft: true
+        #[arg(long)]
+        drafts: bool,
+    },
+    // Clean project
+    Clean { },
+    // Watch for changes and rebuild
+    Watch {
+        // Also write pages with draft: true
+        #[arg(long)]
+        drafts: bool,
+    },
+    // Create a new project
+    New {
+        // Project name
+        name: String,
+        
+        // Use default template
+        #[arg(short, long)]
+        default: bool,
+
+        // Scaffold from a template directory, a git URL, or a built-in template (default, blank)
+        #[arg(short, long)]
+        template: Option<String>,
+
+        // Name for directory index pages instead of index, e.g. "default" for default.md
+        #[arg(long)]
+        index_filename: Option<String>,
+    },
+    // Build the site and serve the output directory over HTTP for local previews
+    Serve {
+        // Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+
+        // Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
+
+        // Rebuild on changes, reloading open pages after each successful rebuild
+        #[arg(short, long)]
+        watch: bool,
+
+        // With --watch, don't inject the live reload script into served pages
+        #[arg(long)]
+        no_live_reload: bool,
+    },
+    // Print a JSON Schema for meowdown-config.yaml (or data/robots_config.yaml with "robots")
+    Schema {
+        #[arg(default_value = "config")]
+        file: String,
+    },
+    // Check the config for unknown keys and conflicting settings without building
+    Check { },
+    // Print the effective configuration and site data after all merging
+    Config {
+        // Variant to resolve (defaults to each configured variant)
+        #[arg(long)]
+        variant: Option<String>,
+
+        // Output format: yaml or json
+        #[arg(short, long, default_value = "yaml")]
+        format: String,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            config_path: None,
+            input_dir: "./".to_string(),
+            output_dir: "output".to_string(),
+            watch: false,
+            variant: None,
+            variants: None,
+            generate_robots_txt: None,
+            generate_sitemap_xml: None,
+            template_dirs: None,
+            preview: None,
+            preview_output_dir: None,
+            strict: None,
+            strict_variables: None,
+            canonical_host: None,
+            heading_shift: None,
+            sort_by: None,
+            pretty_xml: None,
+            default_robots: None,
+            clean_urls: None,
+            trailing_slash: None,
+            head_snippet: None,
+            head_snippet_file: None,
+            body_snippet: None,
+            body_snippet_file: None,
+            snippet_variants: None,
+            posts_dir: None,
+            check_charset: None,
+            utf8_bom: None,
+            since: None,
+            default_changefreq: None,
+            default_priority: None,
+            generate_humans_txt: None,
+            humans_txt: None,
+            generate_security_txt: None,
+            security_txt: None,
+            data_table_hide_empty: None,
+            type_layouts: None,
+            build_revision: None,
+            build_revision_env: None,
+            jobs: None,
+            profile: None,
+            site_url: None,
+            content_dir: None,
+            timezone: None,
+            homepage: None,
+            unsupported_site_data: None,
+            heading_anchors: None,
+            check_links: None,
+            markdown_extensions: None,
+            production_variants: None,
+            env_banner_message: None,
+            env_banner_class: None,
+            env_banner_html: None,
+            tag_url: None,
+            category_url: None,
+            generate_opensearch: None,
+            opensearch: None,
+            changes_json: None,
+            allow_raw_html: None,
+            reading_progress: None,
+            lookup_key: None,
+            variant_languages: None,
+            index_filename: None,
+            share_networks: None,
+            offline_bundle: None,
+            incremental: None,
+            generate_rss: None,
+            excerpt_words: None,
+            sitemap_path: None,
+            robots_path: None,
+            sitemap_lastmod_from_git: None,
+            minify_html: None,
+            fingerprint_assets: None,
+            compile_scss: None,
+            pretty_urls: None,
+            paginate: None,
+            force: None,
+            drafts: None,
+            changed_paths: None,
+            unknown_keys: vec![],
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let mut config: Config = serde_yaml::from_str(&content)?;
+        config.unknown_keys = unknown_config_keys(&content)?.into_iter()
+            .map(|problem| format!("{}: {}", path.display(), problem))
+            .collect();
+        Ok(config)
+    }
+    
+    fn relative_to_config_path(&self, path: &PathBuf) -> PathBuf {
+        if let Some(p) = self.config_path.clone() {
+            if path.as_os_str() == "." || path.as_os_str() == "./" {
+                return PathBuf::from(p.as_str());
+            } else {
+                PathBuf::from(p.as_str()).parent().unwrap().into()
+            }
+        } else {
+            std::env::current_dir().unwrap()
+        }.join(path)
+    }
+    
+    // Template directories in search order, defaulting to templates/ next to the config
+    fn template_search_dirs(&self) -> Vec<PathBuf> {
+        self.template_dirs.clone()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| vec!["templates".to_string()])
+            .iter()
+            .map(|dir| self.relative_to_config_path(&PathBuf::from(dir)))
+            .collect()
+    }
+    
+    // Inline snippet, or the contents of the snippet file (relative to config), unless
+    // snippets are limited to other variants
+    fn load_snippet(&self, inline: &Option<String>, file: &Option<String>) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(variants) = &self.snippet_variants {
+            if !self.variant.as_ref().is_some_and(|v| variants.contains(v)) {
+                return Ok(None);
+            }
+        }
+
+        if let Some(snippet) = inline {
+            Ok(Some(snippet.clone()))
+        } else if let Some(file) = file {
+            let path = self.relative_to_config_path(&PathBuf::from(file));
+            fs::read_to_string(&path)
+                .map(Some)
+                .map_err(|e| format!("Failed to read snippet {}: {}", path.display(), e).into())
+        } else {
+            Ok(None)
+        }
+    }
+    
+    // Name of the page that stands for its directory, e.g. "index" for docs/index.html
+    fn index_name(&self) -> &str {
+        self.index_filename.as_deref().unwrap_or("index")
+    }
+
+    fn index_file_name(&self) -> String {
+        format!("{}.html", self.index_name())
+    }
+
+    fn full_output_path(&self) -> PathBuf {
+        if self.variants.is_some() {
+            panic!("must call build_site_for_each_variant otherwise not sure which to build for");
+        }
+
+        let output_dir = if self.preview.unwrap_or(false) {
+            self.preview_output_dir.clone().unwrap_or_else(|| format!("{}-preview", self.output_dir))
+        } else {
+            self.output_dir.clone()
+        };
+
+        let p = if let Some(variant) = &self.variant {
+            output_dir.replace("{{variant}}", variant)
+        } else {
+            output_dir
+        };
+
+        if p.is_empty() || p == "." || p == "./" {
+            self.config_path.clone().map(PathBuf::from).or(std::env::current_dir().ok()).unwrap()
+        } else {
+            self.relative_to_config_path(&PathBuf::from(&p))
+        }
+    }
+    
+    // One config per variant to build, or just this one without variants
+    fn variant_configs(&self) -> Vec<Config> {
+        match (&self.variant, &self.variants) {
+            (None, Some(variants)) => variants.iter()
+                .map(|variant| Config { variant: Some(variant.clone()), variants: None, .. self.clone() })
+                .collect(),
+            _ => vec![Config { variants: None, .. self.clone() }],
+        }
+    }
+
+    // Fills settings left unset with the profile's presets, so explicit values always win
+    pub fn with_profile_defaults(self) -> Self {
+        match self.profile {
+            Some(Profile::Dev) => Config {
+                site_url: self.site_url.clone().or_else(|| Some("http://localhost:8080".to_string())),
+                pretty_xml: self.pretty_xml.or(Some(true)),
+                minify_html: self.minify_html.or(Some(false)),
+                fingerprint_assets: self.fingerprint_assets.or(Some(false)),
+                .. self
+            },
+            Some(Profile::Prod) => Config {
+                pretty_xml: self.pretty_xml.or(Some(false)),
+                minify_html: self.minify_html.or(Some(true)),
+                fingerprint_assets: self.fingerprint_assets.or(Some(true)),
+                .. self
+            },
+            None => self,
+        }
+    }
+
+    // Checks for settings that make builds misbehave, warning or (under strict) refusing to build
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.check_variants()?;
+        let mut problems = self.unknown_keys.clone();
+
+        // An output directory inside the input is skipped when looking for pages, but one that
+        // holds the pages themselves would mix generated files in with them
+        let input = canonicalize_existing_prefix(&self.full_content_path());
+        let output = canonicalize_existing_prefix(&self.full_output_path());
+        if input.starts_with(&output) {
+            let suggestion = input.parent()
+                .map(|parent| parent.join("output"))
+                .unwrap_or_else(|| PathBuf::from("../output"));
+            problems.push(format!(
+                "output directory {} contains {}, where pages are read from, so builds can pick up generated files; use a sibling output directory such as {}",
+                output.display(), input.display(), suggestion.display()
+            ));
+        }
+
+        if self.strict.unwrap_or(false) && !problems.is_empty() {
+            return Err(problems.join("; ").into());
+        }
+        for problem in problems {
+            eprintln!("Warning: {}", problem);
+        }
+        Ok(())
+    }
+
+    // variant builds one variant and variants builds each listed one, so only one may be set
+    pub fn check_variants(&self) -> Result<(), Box<dyn Error>> {
+        if self.variant.is_some() && self.variants.is_some() {
+            return Err(format!(
+                "both variant and variants are set in {}; use variant to build one variant or variants to build several",
+                self.config_path.as_deref().unwrap_or("the config")
+            ).into());
+        }
+        Ok(())
+    }
+
+    // The environment being built when it isn't production: a variant not listed in
+    // production_variants (default production, prod), else the dev profile
+    pub fn non_production_environment(&self) -> Option<String> {
+        let production_variants = self.production_variants.clone()
+            .unwrap_or_else(|| vec!["production".to_string(), "prod".to_string()]);
+        match (&self.variant, self.profile) {
+            (_, Some(Profile::Prod)) => None,
+            (Some(variant), _) if !production_variants.contains(variant) => Some(variant.clone()),
+            (_, Some(Profile::Dev)) => Some("dev".to_string()),
+            _ => None,
+        }
+    }
+
+    // File extensions treated as markdown pages, without the dot
+    pub fn markdown_extensions(&self) -> Vec<String> {
+        self.markdown_extensions.clone()
+            .filter(|extensions| !extensions.is_empty())
+            .unwrap_or_else(|| vec!["md".to_string()])
+            .into_iter()
+            .map(|ext| ext.trim_start_matches('.').to_string())
+            .collect()
+    }
+
+    // Where the sitemap and robots.txt are written, relative to the output root
+    pub fn sitemap_path(&self) -> String {
+        self.sitemap_path.as_deref().unwrap_or(DEFAULT_SITEMAP_PATH).trim_start_matches('/').to_string()
+    }
+
+    pub fn robots_path(&self) -> String {
+        self.robots_path.as_deref().unwrap_or(DEFAULT_ROBOTS_PATH).trim_start_matches('/').to_string()
+    }
+
+    // Where markdown pages are found: content_dir under the input directory, or the input directory itself
+    pub fn full_content_path(&self) -> PathBuf {
+        match &self.content_dir {
+            Some(dir) => {
+                let dir = match &self.variant {
+                    Some(variant) => dir.replace("{{variant}}", variant),
+                    None => dir.clone(),
+                };
+                self.full_input_path().join(dir)
+            }
+            None => self.full_input_path(),
+        }
+    }
+
+    pub fn full_input_path(&self) -> PathBuf {
+        let p = if let Some(variant) = &self.variant {
+            self.input_dir.replace("{{variant}}", variant)
+        } else {
+            self.input_dir.clone()
+        };
+
+        if p.is_empty() || p == "." || p == "./" {
+            self.config_path.clone().map(PathBuf::from).or(std::env::current_dir().ok()).unwrap()
+        } else {
+            self.relative_to_config_path(&PathBuf::from(&p))
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A scratch project in the temp dir with a config, site data and a bare default layout,
+    // removed again when dropped
+    struct TestSite {
+        root: PathBuf,
+    }
+
+    impl TestSite {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("meowdown-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            let site = Self { root };
+            site.write("meowdown-config.yaml", "")
+                .write("data/site.yaml", "site.url: \"https://www.example.com\"\nsite.title: Test\n")
+                .write("templates/default.tpl.html", "<html><body>{{ content }}</body></html>\n");
+            site
+        }
+
+        fn write(&self, path: &str, content: &str) -> &Self {
+            let path = self.root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+            self
+        }
+
+        // The config file with the paths every test needs in front of the test's own settings
+        fn config_with(&self, settings: &str) -> Config {
+            let content = format!(
+                "config_path: \"{}\"\ninput_dir: \"{}\"\noutput_dir: out\ncontent_dir: content\nwatch: false\n{}",
+                self.root.join("meowdown-config.yaml").display(), self.root.display(), settings
+            );
+            self.write("meowdown-config.yaml", &content);
+            Config::from_file(&self.root.join("meowdown-config.yaml")).unwrap()
+        }
+
+        fn build(&self, settings: &str) -> Result<(), Box<dyn Error>> {
+            build_site(&self.config_with(settings), false)
+        }
+
+        fn read(&self, path: &str) -> String {
+            fs::read_to_string(self.root.join(path)).unwrap_or_else(|e| panic!("{}: {}", path, e))
+        }
+    }
+
+    impl Drop for TestSite {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn output_inside_input_is_not_read_back_as_pages() {
+        let site = TestSite::new("output-inside-input");
+        site.write("index.md", "---\ntitle: Home\n---\nhome\n")
+            .write("out/stale.md", "---\ntitle: Stale\n---\nstale\n");
+        let config = Config { content_dir: None, ..site.config_with("strict: true\n") };
+        config.validate().unwrap();
+        build_site(&config, false).unwrap();
+
+        assert!(site.root.join("out/index.html").exists());
+        assert!(!site.root.join("out/out/stale.html").exists());
+    }
+
+    #[test]
+    fn output_holding_the_pages_fails_strict_validation() {
+        let site = TestSite::new("output-is-input");
+        let config = site.config_with("strict: true\n");
+        let config = Config { output_dir: "content".to_string(), ..config };
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("use a sibling output directory"), "{}", error);
+    }
+
+    // A blog post and an about page whose layout links to both, a stylesheet and another host
+    fn offline_bundle_site(name: &str) -> TestSite {
+        let site = TestSite::new(name);
+        site.write("content/blog/post.md", "---\ntitle: Post\n---\n[About](/about/) [Docs](https://www.example.com/docs/#intro)\n")
+            .write("content/about.md", "---\ntitle: About\n---\nabout\n")
+            .write("content/docs/index.md", "---\ntitle: Docs\n---\ndocs\n")
+            .write("assets/style.css", "body {}\n")
+            .write("templates/default.tpl.html", "<html><head><link href=\"/assets/style.css\"><a href='https://other.example.org/x'>x</a></head><body>{{ content }}</body></html>\n");
+        site
+    }
+
+    #[test]
+    fn offline_bundle_links_nested_pages_relatively() {
+        let site = offline_bundle_site("offline-nested");
+        site.build("offline_bundle: true\n").unwrap();
+
+        let html = site.read("out/blog/post.html");
+        assert!(html.contains("href=\"../about.html\""), "{}", html);
+        assert!(html.contains("href=\"../docs/index.html#intro\""), "{}", html);
+        assert!(html.contains("href=\"../assets/style.css\""), "{}", html);
+        assert!(html.contains("href='https://other.example.org/x'"), "{}", html);
+        assert!(site.read("out/about.html").contains("href=\"assets/style.css\""));
+    }
+
+    #[test]
+    fn offline_bundle_follows_the_files_clean_urls_wrote() {
+        let site = offline_bundle_site("offline-clean-urls");
+        site.build("offline_bundle: true\nclean_urls: true\ntrailing_slash: true\n").unwrap();
+        let html = site.read("out/blog/post.html");
+        assert!(html.contains("href=\"../about.html\""), "{}", html);
+        assert!(html.contains("href=\"../docs/index.html#intro\""), "{}", html);
+
+        let site = offline_bundle_site("offline-pretty-urls");
+        site.build("offline_bundle: true\npretty_urls: true\n").unwrap();
+        let html = site.read("out/blog/post/index.html");
+        assert!(html.contains("href=\"../../about/index.html\""), "{}", html);
+        assert!(html.contains("href=\"../../assets/style.css\""), "{}", html);
+    }
+
+    #[test]
+    fn raw_blocks_in_markdown_are_not_parsed() {
+        let site = TestSite::new("raw-markdown");
+        site.write("content/page.md", "---\ntitle: Raw\n---\nA {{ raw }}{{ get title }}{{ endraw }}\n\n{{ raw }}\nline {{ title }} and {title}\n\nmore {{ if x }}y{{ endif }}\n{{ endraw }}\n\nafter\n");
+        site.build("").unwrap();
+
+        let html = site.read("out/page.html");
+        assert!(html.contains("A {{ get title }}"), "{}", html);
+        assert!(html.contains("line {{ title }} and {title}"), "{}", html);
+        assert!(html.contains("more {{ if x }}y{{ endif }}"), "{}", html);
+        assert!(html.contains("after"), "{}", html);
+        assert!(!html.contains("raw }}"), "{}", html);
+    }
+
+    #[test]
+    fn escaped_delimiters_survive_verbatim() {
+        let site = TestSite::new("raw-escapes");
+        site.write("content/page.md", "---\ntitle: Escapes\n---\nEscaped: \\{{ if foo }} done\n\nInline `\\{{ title \\}}` code\n\nafter\n")
+            .write("templates/default.tpl.html", "<html><body>\\{{ content }} {{ raw }}{{ title }}{{ endraw }} {{ content }}</body></html>\n");
+        site.build("strict_variables: true\nstrict: true\n").unwrap();
+
+        let html = site.read("out/page.html");
+        assert!(html.contains("Escaped: {{ if foo }} done"), "{}", html);
+        assert!(html.contains("<code>{{ title }}</code>"), "{}", html);
+        assert!(html.contains("<body>{{ content }} {{ title }} <p>"), "{}", html);
+        assert!(html.contains("after"), "{}", html);
+        assert!(!html.contains(RAW_OPEN_BRACE) && !html.contains(RAW_CLOSE_BRACE));
+    }
+
+    #[test]
+    fn minify_html_collapses_whitespace_and_keeps_preformatted_text() {
+        let html = "<!DOCTYPE html>\n<html>\n  <head>\n    <title>A   page</title>\n  </head>\n  <body>\n    <!-- note -->\n    <p>Some   <em>spaced</em>   text</p>\n    <pre>  keep\n   this</pre>\n    <script>let  x = 1;</script>\n  </body>\n</html>\n";
+        let minified = minify_html(html);
+        assert!(minified.to_ascii_lowercase().starts_with("<!doctype html><html><head>"), "{}", minified);
+        assert!(minified.contains("<p>Some <em>spaced</em> text</p>"), "{}", minified);
+        assert!(minified.contains("<pre>  keep\n   this</pre>"), "{}", minified);
+        assert!(minified.contains("<script>let  x = 1;</script>"), "{}", minified);
+        assert!(minified.contains("</body></html>"), "{}", minified);
+        assert!(!minified.contains("note"), "{}", minified);
+    }
+
+    #[test]
+    fn minify_html_setting_minifies_built_pages() {
+        let site = TestSite::new("minify-html");
+        site.write("content/page.md", "---\ntitle: Minified\n---\n# Heading\n\nSome   text\n")
+            .write("templates/default.tpl.html", "<html>\n  <body>\n    {{ content }}\n  </body>\n</html>\n");
+        site.build("minify_html: true\n").unwrap();
+        assert_eq!(site.read("out/page.html"), "<html><body><h1>Heading</h1><p>Some text</p></body></html>");
+    }
+
+    #[test]
+    fn truncate_html_closes_open_tags_at_a_word_break() {
+        assert_eq!(truncate_html("<p>Short</p>", 20), "<p>Short</p>");
+        assert_eq!(truncate_html("<p>One <em>two three</em> four</p>", 10), "<p>One <em>two…</em></p>");
+    }
+
+    #[test]
+    fn slugify_transliterates_accents_and_keeps_other_scripts() {
+        assert_eq!(slugify("Héllo, World!"), "hello-world");
+        assert_eq!(slugify("Cre\u{301}me Brûlée"), "creme-brulee");
+        assert_eq!(slugify("Straße & Œuvre"), "strasse-oeuvre");
+        assert_eq!(slugify("Привет мир"), "привет-мир");
+        assert_eq!(slugify("日本語 テキスト"), "日本語-テキスト");
+    }
+
+    #[test]
+    fn slugify_collapses_punctuation_and_repeated_separators() {
+        assert_eq!(slugify("What's new?"), "what-s-new");
+        assert_eq!(slugify("  --Rust -- & __Go__!!  "), "rust-go");
+        assert_eq!(slugify("a...b///c"), "a-b-c");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    #[test]
+    fn profiles_fill_only_unset_settings() {
+        let site = TestSite::new("profiles");
+        let dev = Config { profile: Some(Profile::Dev), ..site.config_with("") }.with_profile_defaults();
+        assert_eq!((dev.minify_html, dev.fingerprint_assets, dev.pretty_xml), (Some(false), Some(false), Some(true)));
+
+        let prod = Config { profile: Some(Profile::Prod), ..site.config_with("fingerprint_assets: false\n") }.with_profile_defaults();
+        assert_eq!((prod.minify_html, prod.fingerprint_assets, prod.pretty_xml), (Some(true), Some(false), Some(false)));
+    }
+
+    #[test]
+    fn redirects_are_written_inside_the_output_directory() {
+        let site = TestSite::new("redirects");
+        site.write("content/new.md", "---\ntitle: New\n---\nnew\n")
+            .write("data/redirects.yaml", "/old/: /new.html\nold.html: /new.html\n");
+        site.build("").unwrap();
+        assert!(site.read("out/old/index.html").contains("url=https://www.example.com/new.html"));
+        assert!(site.read("out/old.html").contains("url=https://www.example.com/new.html"));
+
+        for from in ["../escape", "/docs/../../escape.html"] {
+            site.write("data/redirects.yaml", &format!("\"{}\": /new.html\n", from));
+            let error = site.build("").unwrap_err().to_string();
+            assert!(error.contains("must stay inside the output directory"), "{}", error);
+        }
+        assert!(!site.root.join("escape").exists() && !site.root.join("escape.html").exists());
+    }
+
+    #[test]
+    fn since_builds_check_redirects_against_pages_they_skip() {
+        let site = TestSite::new("redirects-since");
+        site.write("content/about.md", "---\ntitle: About\n---\nabout\n")
+            .write("data/redirects.yaml", "/about.html: /new.html\n");
+        site.build("").unwrap();
+        let about = site.read("out/about.html");
+
+        let config = Config { since: Some(Utc::now() + chrono::Duration::hours(1)), ..site.config_with("") };
+        build_site(&config, false).unwrap();
+        assert_eq!(site.read("out/about.html"), about);
+    }
+
+    #[test]
+    fn variants_build_into_their_own_or_a_shared_output_directory() {
+        let site = TestSite::new("variants");
+        site.write("content/page.md", "---\ntitle: Page\n---\nvariant {{ variant }}\n");
+        let config = site.config_with("variants: [a, b, c]\njobs: 3\n");
+        build_site_for_each_variant(&Config { output_dir: "out/{{variant}}".to_string(), ..config.clone() }, false).unwrap();
+        for variant in ["a", "b", "c"] {
+            assert!(site.read(&format!("out/{}/page.html", variant)).contains(&format!("variant {}", variant)));
+        }
+
+        assert_eq!(config.variant_configs().len(), 3);
+        build_site_for_each_variant(&config, false).unwrap();
+        assert!(site.read("out/page.html").contains("variant c"));
+    }
+
+    #[test]
+    fn missing_include_fails_the_build_naming_both_files() {
+        let site = TestSite::new("missing-include");
+        site.write("content/page.md", "---\ntitle: Page\n---\nbefore {{ include \"partials/nav.html\" }} after\n")
+            .write("templates/partials/footer.html", "footer {{ include \"partials/missing.html\" }}\n");
+        let error = site.build("").unwrap_err().to_string();
+        assert!(error.contains("includes partials/nav.html"), "{}", error);
+        assert!(error.contains("content/page.md"), "{}", error);
+
+        site.write("content/page.md", "---\ntitle: Page\n---\n{{ include \"partials/footer.html\" }}\n");
+        let error = site.build("").unwrap_err().to_string();
+        assert!(error.starts_with("partials/footer.html includes partials/missing.html"), "{}", error);
+    }
+
+    #[test]
+    fn watch_rebuilds_only_pages_using_a_changed_file() {
+        let site = TestSite::new("watch-rebuild");
+        site.write("content/a.md", "---\ntitle: A\n---\na\n")
+            .write("content/b.md", "---\ntitle: B\n---\nb {{ include \"partials/note.html\" }}\n")
+            .write("templates/partials/note.html", "note\n");
+        let rebuild = |changed: &[&str]| {
+            let changed_paths = changed.iter().map(|path| site.root.join(path).canonicalize().unwrap()).collect();
+            build_site(&Config { changed_paths: Some(changed_paths), ..site.config_with("") }, false).unwrap();
+        };
+        let mark_outputs = || {
+            site.write("out/a.html", "old").write("out/b.html", "old");
+        };
+        rebuild(&[]);
+        assert!(site.read("out/b.html").contains("note"));
+
+        mark_outputs();
+        rebuild(&["content/a.md"]);
+        assert!(site.read("out/a.html").contains("<p>a</p>"));
+        assert_eq!(site.read("out/b.html"), "old");
+
+        mark_outputs();
+        rebuild(&["templates/partials/note.html"]);
+        assert_eq!(site.read("out/a.html"), "old");
+        assert!(site.read("out/b.html").contains("note"));
+
+        mark_outputs();
+        rebuild(&["templates/default.tpl.html"]);
+        assert!(site.read("out/a.html").contains("<p>a</p>") && site.read("out/b.html").contains("note"));
+    }
+
+    #[test]
+    fn directory_site_strings_reach_functions_but_not_front_matter() {
+        let site = TestSite::new("directory-strings");
+        site.write("content/docs/_site.yaml", "site.title: Docs\nsite.tagline: \"\"\n")
+            .write("content/docs/guide.md", "---\ntitle: Guide\n---\n[{{ site.title }}] [{{ get site.title }}] [{{ default site.tagline \"none\" }}] [{{ slugify site.title }}] {{ if site.title == \"Docs\" }}docs{{ endif }}\n")
+            .write("content/index.md", "---\ntitle: Home\n---\n[{{ site.title }}] [{{ slugify site.title }}]\n{{ list_md \"docs\" }}\n[{{ get site.title }}]\n");
+        site.build("").unwrap();
+
+        let guide = site.read("out/docs/guide.html");
+        assert!(guide.contains("[Docs] [Docs] [none] [docs] docs"), "{}", guide);
+        let home = site.read("out/index.html");
+        assert!(home.contains("[Test] [test]"), "{}", home);
+        assert!(!home.contains("[Docs]"), "{}", home);
+    }
+
+    #[test]
+    fn default_layout_loads_reading_progress_only_when_enabled() {
+        let site = TestSite::new("reading-progress");
+        site.write("data/site.yaml", "site.url: \"https://www.example.com/blog/\"\nsite.title: Test\n")
+            .write("templates/default.tpl.html", include_str!("../_default-data/templates/default_layout.tpl.html"))
+            .write("content/post.md", "---\ntitle: Post\n---\n# Post\n");
+        site.build("").unwrap();
+        assert!(!site.read("out/post.html").contains("reading-progress.js"));
+
+        site.build("reading_progress: true\n").unwrap();
+        let html = site.read("out/post.html");
+        assert!(html.contains("<script src=\"https://www.example.com/blog/assets/reading-progress.js\" defer></script>"), "{}", html);
+    }
+
+    #[test]
+    fn tags_and_categories_html_render_each_item() {
+        let site = TestSite::new("tags-html");
+        site.write("content/list.md", "---\ntitle: List\ntags: [rust, \"Q&A\", \" \"]\ncategories:\n  - Tools\n---\n{{ tags_html }}{{ categories_html }}\n")
+            .write("content/scalar.md", "---\ntitle: Scalar\ntags: \"a, b,,\"\n---\n{{ tags_html }}{{ categories_html }}\n");
+        site.build("").unwrap();
+
+        let list = site.read("out/list.html");
+        assert!(list.contains("<p><b>Tags:</b> rust, Q&amp;A</p><p><b>Categories:</b> Tools</p>"), "{}", list);
+        let scalar = site.read("out/scalar.html");
+        assert!(scalar.contains("<p><b>Tags:</b> a, b</p>"), "{}", scalar);
+        assert!(!scalar.contains("Categories"), "{}", scalar);
+    }
+
+    #[test]
+    fn drafts_stay_out_of_the_sitemap_and_feed_and_are_removed_when_off() {
+        let site = TestSite::new("drafts");
+        site.write("content/post.md", "---\ntitle: Post\ndate: 2024-01-02\n---\npost\n")
+            .write("content/wip.md", "---\ntitle: Work in progress\ndate: 2024-01-03\ndraft: true\n---\nwip\n");
+        let settings = "generate_sitemap_xml: true\ngenerate_rss: true\n";
+        build_site(&Config { drafts: Some(true), ..site.config_with(settings) }, false).unwrap();
+        assert!(site.read("out/wip.html").contains("wip"));
+        let sitemap = site.read("out/sitemap.xml");
+        assert!(sitemap.contains("post.html") && !sitemap.contains("wip.html"), "{}", sitemap);
+        let feed = site.read(&format!("out/{}", RSS_FEED_PATH));
+        assert!(feed.contains("Post") && !feed.contains("Work in progress"), "{}", feed);
+
+        site.build(settings).unwrap();
+        assert!(!site.root.join("out/wip.html").exists());
+        assert!(site.root.join("out/post.html").exists());
+    }
+
+    #[test]
+    fn missing_layouts_are_build_errors() {
+        let site = TestSite::new("missing-layout");
+        site.write("content/page.md", "---\ntitle: Page\nlayout: post\n---\npage\n");
+        let error = site.build("").unwrap_err().to_string();
+        assert!(error.contains("uses layout post, but layout post is missing"), "{}", error);
+
+        site.write("templates/post.tpl.html", "---\nlayout: base\n---\n{{ content }}\n");
+        let error = site.build("").unwrap_err().to_string();
+        assert!(error.contains("layout post extends base, but layout base is missing"), "{}", error);
+    }
+
+    #[test]
+    fn sites_without_site_url_build_with_site_relative_links() {
+        let site = TestSite::new("no-site-url");
+        site.write("data/site.yaml", "site.title: Test\n")
+            .write("content/docs/page.md", "---\ntitle: Page\n---\n[About](/about.html) [Top](#top) [Out](https://other.example.org/)\n\n{{ permalink }}|{{ relative-url \"/feed.xml\" }}|\n")
+            .write("content/about.md", "---\ntitle: About\n---\nabout\n");
+        site.build("generate_sitemap_xml: true\ngenerate_robots_txt: true\n").unwrap();
+
+        let html = site.read("out/docs/page.html");
+        assert!(html.contains("href=\"/about.html\""), "{}", html);
+        assert!(html.contains("href=\"#top\"") && html.contains("href=\"https://other.example.org/\""), "{}", html);
+        assert!(html.contains("|/feed.xml|"), "{}", html);
+        assert!(site.read("out/sitemap.xml").contains("<loc>/docs/page.html</loc>"));
+    }
+
+    #[test]
+    fn unknown_config_keys_report_their_line_and_a_suggestion() {
+        let problems = unknown_config_keys("output_dir: out\n# a comment\ngenerate_sitmap_xml: true\n\"colour_scheme\": dark\n").unwrap();
+        assert_eq!(problems, vec![
+            "unknown config key generate_sitmap_xml on line 3, did you mean generate_sitemap_xml?",
+            "unknown config key colour_scheme on line 4",
+        ]);
+
+        let site = TestSite::new("unknown-keys");
+        let config = site.config_with("pretty_xlm: true\n");
+        config.validate().unwrap();
+        let error = Config { strict: Some(true), ..config }.validate().unwrap_err().to_string();
+        assert!(error.contains("unknown config key pretty_xlm on line 6, did you mean pretty_xml?"), "{}", error);
+    }
+
+    #[test]
+    fn variant_and_variants_together_are_rejected() {
+        let site = TestSite::new("variant-and-variants");
+        let config = site.config_with("variant: staging\nvariants: [staging, production]\n");
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("both variant and variants are set"), "{}", error);
+        assert!(build_site_for_each_variant(&config, false).is_err());
+    }
+
+    #[test]
+    fn config_reloads_pick_up_edits_and_keep_the_last_good_config() {
+        let site = TestSite::new("config-reload");
+        let config_file = site.root.join("meowdown-config.yaml");
+        let mut config = site.config_with("template_dirs: [templates]\n");
+        let reloader = ConfigReloader { file: Some(config_file.clone()), load: &|| Config::from_file(&config_file) };
+
+        site.config_with("template_dirs: [layouts]\npretty_xml: true\n");
+        reloader.reload(&mut config).unwrap();
+        assert_eq!(config.pretty_xml, Some(true));
+        assert_eq!(config.template_dirs, Some(vec!["layouts".to_string()]));
+
+        site.write("meowdown-config.yaml", "output_dir: [unclosed\n");
+        assert!(reloader.reload(&mut config).is_err());
+        assert_eq!(config.pretty_xml, Some(true));
+        assert_eq!(config.template_dirs, Some(vec!["layouts".to_string()]));
+    }
+
+    #[test]
+    fn pretty_urls_give_pages_their_own_directory() {
+        let site = TestSite::new("pretty-urls");
+        site.write("content/about.md", "---\ntitle: About\n---\nurl {{ page.url }}\n")
+            .write("content/index.md", "---\ntitle: Home\n---\nurl {{ page.url }}\n")
+            .write("content/docs/index.md", "---\ntitle: Docs\n---\nurl {{ page.url }}\n")
+            .write("content/docs/intro.md", "---\ntitle: Intro\n---\nurl {{ page.url }}\n");
+        site.build("pretty_urls: true\ngenerate_sitemap_xml: true\n").unwrap();
+
+        assert!(site.read("out/about/index.html").contains("url https://www.example.com/about/"));
+        assert!(site.read("out/docs/intro/index.html").contains("url https://www.example.com/docs/intro/"));
+        assert!(site.read("out/index.html").contains("url https://www.example.com/"));
+        assert!(site.read("out/docs/index.html").contains("url https://www.example.com/docs/"));
+        assert!(!site.root.join("out/about.html").exists() && !site.root.join("out/index/index.html").exists());
+
+        let sitemap = site.read("out/sitemap.xml");
+        assert!(sitemap.contains("<loc>https://www.example.com/about/</loc>"), "{}", sitemap);
+        assert!(sitemap.contains("<loc>https://www.example.com/docs/</loc>"), "{}", sitemap);
+    }
+
+    #[test]
+    fn paginated_listings_split_entries_across_pages() {
+        let site = TestSite::new("paginate");
+        for i in 1..=25 {
+            site.write(&format!("content/posts/p{:02}.md", i), &format!("---\ntitle: Post {}\n---\npost\n", i));
+        }
+        site.write("templates/item.tpl.html", "[{{ title }}]")
+            .write("content/blog/index.md", "---\ntitle: Blog\npaginate: 10\n---\n{{ list_md \"content/posts\" \"item\" }}\n\n{{ page_number }}/{{ total_pages }} prev={{ prev_url }} next={{ next_url }}\n");
+        site.build("").unwrap();
+
+        let first = site.read("out/blog/index.html");
+        assert!(first.contains("[Post 1]") && first.contains("[Post 10]") && !first.contains("[Post 11]"), "{}", first);
+        assert!(first.contains("1/3 prev= next=https://www.example.com/blog/page/2/index.html</p>"), "{}", first);
+        let second = site.read("out/blog/page/2/index.html");
+        assert!(second.contains("[Post 11]") && second.contains("[Post 20]") && !second.contains("[Post 10]"), "{}", second);
+        assert!(second.contains("2/3 prev=https://www.example.com/blog/index.html next=https://www.example.com/blog/page/3/index.html</p>"), "{}", second);
+        let third = site.read("out/blog/page/3/index.html");
+        assert!(third.contains("[Post 21]") && third.contains("[Post 25]") && !third.contains("[Post 20]"), "{}", third);
+        assert!(third.contains("3/3 prev=https://www.example.com/blog/page/2/index.html next=</p>"), "{}", third);
+        assert!(!site.root.join("out/blog/page/4").exists());
+    }
+
+    #[test]
+    fn list_md_sorts_by_date_and_limits() {
+        let site = TestSite::new("list-md-sort");
+        site.write("content/posts/a.md", "---\ntitle: A\ndate: 2024-03-01\n---\na\n")
+            .write("content/posts/b.md", "---\ntitle: B\ndate: 2024-11-15\n---\nb\n")
+            .write("content/posts/c.md", "---\ntitle: C\ndate: 2023-12-31\n---\nc\n")
+            .write("content/posts/d.md", "---\ntitle: D\ndate: 2024-04-09\n---\nd\n")
+            .write("templates/item.tpl.html", "[{{ title }}]")
+            .write("content/index.md", "---\ntitle: Home\n---\n{{ list_md \"content/posts\" \"item\" \"date:desc\" \"3\" }}\n\n{{ list_md \"content/posts\" \"item\" \"date\" }}\n");
+        site.build("").unwrap();
+
+        let titles = |html: &str| html.split('[').skip(1).map(|rest| rest[..1].to_string()).collect::<String>();
+        let html = site.read("out/index.html");
+        let (newest, oldest) = html.split_once("</p>").unwrap();
+        assert_eq!(titles(newest), "BDA");
+        assert_eq!(titles(oldest), "CADB");
+    }
+
+    #[test]
+    fn truncation_counts_characters_not_bytes() {
+        // Ten characters, 30 bytes
+        let cjk = "日本語のテキストです";
+        assert_eq!(truncate_chars(cjk, 10), cjk);
+        assert_eq!(truncate_chars(cjk, 9), "日本語のテキストで…");
+        assert_eq!(truncate_chars("crème brûlée", 12), "crème brûlée");
+        assert_eq!(truncate_chars("crème brûlée", 11), "crème…");
+        assert_eq!(truncate_chars("crème brûlée", 5), "crème…");
+        assert_eq!(truncate_words("naïve café au lait", 2), "naïve café…");
+        assert_eq!(truncate_words("naïve café", 2), "naïve café");
+
+        let site = TestSite::new("truncate");
+        site.write("content/page.md", "---\ntitle: Page\ndescription: Ça coûte très cher\n---\n[{{ truncate description 10 }}] [{{ truncatewords \"un deux trois\" 2 }}]\n");
+        site.build("").unwrap();
+        assert!(site.read("out/page.html").contains("[Ça coûte…] [un deux…]"));
+    }
+
+    #[test]
+    fn default_falls_back_for_absent_and_empty_keys() {
+        let site = TestSite::new("default");
+        site.write("content/page.md", "---\ntitle: Page\nauthor: Jane\neditor: \"\"\nreviewer: \"  \"\n---\n[{{ default author \"Anonymous\" }}] [{{ default editor \"Nobody\" }}] [{{ default reviewer \"Nobody\" }}] [{{ default missing \"Fallback\" }}] [{{ default site.title \"Untitled\" }}] [{{ get editor \"Nobody\" }}]\n");
+        site.build("").unwrap();
+        let html = site.read("out/page.html");
+        assert!(html.contains("[Jane] [Nobody] [Nobody] [Fallback] [Test] []"), "{}", html);
+    }
+
+    #[test]
+    fn sitemap_and_feed_are_well_formed_pretty_or_compact() {
+        let site = TestSite::new("pretty-xml");
+        site.write("content/post.md", "---\ntitle: Fish & Chips <3\ndate: 2024-05-01\n---\npost\n")
+            .write("content/about.md", "---\ntitle: About\n---\nabout\n");
+        for pretty in [true, false] {
+            site.build(&format!("pretty_xml: {}\ngenerate_sitemap_xml: true\ngenerate_rss: true\n", pretty)).unwrap();
+            for file in ["out/sitemap.xml".to_string(), format!("out/{}", RSS_FEED_PATH)] {
+                let xml = site.read(&file);
+                let document = roxmltree::Document::parse(&xml).unwrap_or_else(|e| panic!("{}: {}\n{}", file, e, xml));
+                assert!(document.descendants().any(|node| node.has_tag_name("loc") || node.has_tag_name("item")), "{}", xml);
+                assert_eq!(xml.contains("\n  <"), pretty, "{}", xml);
+            }
+        }
+        let sitemap = site.read("out/sitemap.xml");
+        assert_eq!(sitemap.trim_end().lines().count(), 1, "{}", sitemap);
+    }
+
+    #[test]
+    fn sitemap_locs_match_page_urls_with_and_without_clean_urls() {
+        let site = TestSite::new("sitemap-locs");
+        site.write("content/about.md", "---\ntitle: About\n---\n{{ page.url }}\n")
+            .write("content/docs/index.md", "---\ntitle: Docs\n---\n{{ page.url }}\n");
+        let cases = [
+            ("", "https://www.example.com/about.html", "https://www.example.com/docs/index.html"),
+            ("clean_urls: true\n", "https://www.example.com/about", "https://www.example.com/docs/"),
+            ("clean_urls: true\ntrailing_slash: true\n", "https://www.example.com/about/", "https://www.example.com/docs/"),
+        ];
+        for (settings, about, docs) in cases {
+            site.build(&format!("generate_sitemap_xml: true\n{}", settings)).unwrap();
+            let sitemap = site.read("out/sitemap.xml");
+            assert!(sitemap.contains(&format!("<loc>{}</loc>", about)), "{}\n{}", settings, sitemap);
+            assert!(sitemap.contains(&format!("<loc>{}</loc>", docs)), "{}\n{}", settings, sitemap);
+            assert!(site.read("out/about.html").contains(about));
+            assert!(site.read("out/docs/index.html").contains(docs));
+        }
+    }
+
+    #[test]
+    fn site_data_handles_numbers_sequences_mappings_and_nulls() {
+        let site = TestSite::new("site-data-types");
+        site.write("data/site.yaml", "site.url: \"https://www.example.com\"\nsite.title: Test\nyear: 2024\nratio: 1.5\nlanguages: [en, fr, 3]\nsocial:\n  github: me\n  links:\n    home: /\nlicense: null\n")
+            .write("content/page.md", "---\ntitle: Page\n---\n[{{ year }}] [{{ ratio }}] [{{ languages }}] [{{ social.github }}] [{{ social.links.home }}] [{{ get license \"none\" }}]\n");
+        site.build("").unwrap();
+        let html = site.read("out/page.html");
+        assert!(html.contains("[2024] [1.5] [en, fr, 3] [me] [/] [none]"), "{}", html);
+
+        let error = site.build("unsupported_site_data: error\n").unwrap_err().to_string();
+        assert_eq!(error, "site data license has unsupported type null");
+        let error = site.build("strict: true\n").unwrap_err().to_string();
+        assert_eq!(error, "site data license has unsupported type null");
+
+        site.write("data/site.yaml", "site.url: \"https://www.example.com\"\nnested: [[a, b]]\n");
+        let error = site.build("unsupported_site_data: error\n").unwrap_err().to_string();
+        assert_eq!(error, "site data nested has unsupported type sequence with null or nested items");
+    }
+
+    #[test]
+    fn each_variant_gets_its_own_robots_txt_and_sitemap() {
+        let site = TestSite::new("variant-robots");
+        site.write("content/page.md", "---\ntitle: Page\n---\npage\n")
+            .write("data/site.a.yaml", "site.url: \"https://a.example.com\"\n")
+            .write("data/site.b.yaml", "site.url: \"https://b.example.com\"\n")
+            .write("data/robots_config.yaml", "sitemap: /sitemap.xml\ncrawl_delay: 5\nglobal_rules:\n  disallow: [/private/]\n")
+            .write("data/robots_config.b.yaml", "crawl_delay: 10\n");
+        let config = site.config_with("variants: [a, b]\ngenerate_sitemap_xml: true\ngenerate_robots_txt: true\n");
+        build_site_for_each_variant(&Config { output_dir: "out/{{variant}}".to_string(), ..config }, false).unwrap();
+
+        for (variant, crawl_delay) in [("a", 5), ("b", 10)] {
+            let robots = site.read(&format!("out/{}/robots.txt", variant));
+            assert!(robots.contains(&format!("Sitemap: https://{}.example.com/sitemap.xml", variant)), "{}", robots);
+            assert!(robots.contains(&format!("Crawl-delay: {}", crawl_delay)), "{}", robots);
+            let sitemap = site.read(&format!("out/{}/sitemap.xml", variant));
+            assert!(sitemap.contains(&format!("<loc>https://{}.example.com/page.html</loc>", variant)), "{}", sitemap);
+        }
+    }
+
+    #[test]
+    fn html_lang_and_dir_follow_the_variant() {
+        let site = TestSite::new("variant-languages");
+        site.write("templates/default.tpl.html", "<html lang=\"{{ html_lang }}\" dir=\"{{ html_dir }}\"><body>{{ content }}</body></html>\n")
+            .write("content/page.md", "---\ntitle: Page\n---\npage\n");
+        let config = site.config_with("variants: [ar, fr, de]\nvariant_languages:\n  ar: { lang: ar, dir: rtl }\n  fr: { lang: fr }\n");
+        build_site_for_each_variant(&Config { output_dir: "out/{{variant}}".to_string(), ..config }, false).unwrap();
+
+        assert!(site.read("out/ar/page.html").starts_with("<html lang=\"ar\" dir=\"rtl\">"));
+        assert!(site.read("out/fr/page.html").starts_with("<html lang=\"fr\" dir=\"ltr\">"));
+        assert!(site.read("out/de/page.html").starts_with("<html lang=\"en\" dir=\"ltr\">"));
+    }
+
+    #[test]
+    fn nested_if_and_foreach_blocks_close_at_their_own_depth() {
+        let site = TestSite::new("nested-blocks");
+        site.write("templates/default.tpl.html", concat!(
+                "<{{ if a }}1{{ if c }}2{{ endif }}3{{ endif }}>",
+                "<{{ if a }}A{{ if b }}B{{ if c }}C{{ endif }}b{{ endif }}a{{ endif }}>",
+                "<{{ if c }}x{{ if a }}y{{ endif }}z{{ endif }}>",
+                "<{{ if a }}{{ foreach items as item }}({{ item }}{{ if b }}!{{ endif }}){{ endforeach }}{{ endif }}>\n",
+            ))
+            .write("content/page.md", "---\ntitle: Page\na: yes\nb: yes\nitems: [x, y]\n---\npage\n");
+        site.build("").unwrap();
+
+        assert_eq!(site.read("out/page.html"), "<13><ABba><><(x!)(y!)>\n");
+    }
+
+    #[test]
+    fn index_filename_replaces_index_for_clean_urls_and_redirects() {
+        let site = TestSite::new("index-filename");
+        site.write("content/default.md", "---\ntitle: Home\n---\nhome\n")
+            .write("content/docs/default.md", "---\ntitle: Docs\n---\n[{{ permalink }}]\n")
+            .write("content/about.md", "---\ntitle: About\n---\nabout\n")
+            .write("data/redirects.yaml", "/old/: /about.html\n");
+        site.build("index_filename: default\nclean_urls: true\ngenerate_sitemap_xml: true\n").unwrap();
+
+        let html = site.read("out/docs/default.html");
+        assert!(html.contains("[https://www.example.com/docs/]"), "{}", html);
+        assert!(site.read("out/old/default.html").contains("url=https://www.example.com/about"));
+        assert!(!site.root.join("out/old/index.html").exists());
+        let sitemap = site.read("out/sitemap.xml");
+        for loc in ["https://www.example.com/", "https://www.example.com/docs/", "https://www.example.com/about"] {
+            assert!(sitemap.contains(&format!("<loc>{}</loc>", loc)), "{}", sitemap);
+        }
+    }
+
+    #[test]
+    fn elseif_renders_the_first_matching_branch() {
+        let site = TestSite::new("elseif");
+        site.write("templates/default.tpl.html", concat!(
+                "<{{ if a }}A{{ elseif b }}B{{ elseif c }}C{{ elseif d }}D{{ else }}E{{ endif }}>",
+                "<{{ if a }}A{{ elseif x }}X{{ elseif y }}Y{{ else }}E{{ endif }}>",
+                "<{{ if a }}A{{ elseif c }}{{ if d }}CD{{ else }}C{{ endif }}{{ elseif d }}D{{ endif }}>\n",
+            ))
+            .write("content/page.md", "---\ntitle: Page\nc: yes\nd: yes\n---\npage\n");
+        site.build("").unwrap();
+
+        assert_eq!(site.read("out/page.html"), "<C><E><CD>\n");
+    }
+
+    #[test]
+    fn share_buttons_link_the_encoded_permalink_and_title() {
+        let site = TestSite::new("share-buttons");
+        site.write("templates/default.tpl.html", "<html><body>[{{ share_buttons }}]</body></html>\n")
+            .write("content/docs/page.md", "---\ntitle: \"Tea & Cake\"\n---\npage\n");
+        site.build("share_networks:\n  - name: X\n  - name: Email\n  - name: Custom Site\n    url: \"https://share.example.org/?u={url}&t={title}\"\n").unwrap();
+
+        let html = site.read("out/docs/page.html");
+        let url = "https%3A%2F%2Fwww.example.com%2Fdocs%2Fpage.html";
+        assert!(html.contains(&format!("<a class=\"share-x\" href=\"https://twitter.com/intent/tweet?url={}&amp;text=Tea%20%26%20Cake\"", url)), "{}", html);
+        assert!(html.contains(&format!("<a class=\"share-email\" href=\"mailto:?subject=Tea%20%26%20Cake&amp;body={}\"", url)), "{}", html);
+        assert!(html.contains(&format!("<a class=\"share-custom-site\" href=\"https://share.example.org/?u={}&amp;t=Tea%20%26%20Cake\"", url)), "{}", html);
+
+        site.build("share_networks: []\n").unwrap();
+        assert!(site.read("out/docs/page.html").contains("[]"));
+    }
+
+    #[test]
+    fn if_conditions_compare_and_negate() {
+        let site = TestSite::new("if-conditions");
+        site.write("templates/default.tpl.html", concat!(
+                "<{{ if status == \"published\" }}eq{{ endif }}{{ if status == \"draft\" }}!eq{{ endif }}>",
+                "<{{ if lang != \"en\" }}ne{{ endif }}{{ if lang != \"fr\" }}!ne{{ endif }}>",
+                "<{{ if !missing }}not-missing{{ endif }}{{ if !status }}!not{{ endif }}>",
+                "<{{ if off }}off{{ endif }}{{ if zero }}zero{{ endif }}{{ if empty }}empty{{ endif }}{{ if !off }}not-off{{ endif }}>",
+                "<{{ if missing != \"en\" }}missing-ne{{ endif }}{{ if missing == \"\" }}!missing-eq{{ endif }}>\n",
+            ))
+            .write("content/page.md", "---\ntitle: Page\nstatus: published\nlang: fr\noff: false\nzero: 0\nempty: \"\"\n---\npage\n");
+        site.build("").unwrap();
+
+        assert_eq!(site.read("out/page.html"), "<eq><ne><not-missing><not-off><missing-ne>\n");
+    }
+
+    #[test]
+    fn rss_feed_lists_posts_newest_first() {
+        let site = TestSite::new("rss-feed");
+        site.write("data/site.yaml", "site.url: \"https://www.example.com\"\nsite.title: Test & Co\nsite.description: A test site\n")
+            .write("content/posts/old.md", "---\ntitle: Old\ndate: 2024-01-02\n---\nThe *old* post body.\n")
+            .write("content/posts/new.md", "---\ntitle: New\ndate: 2024-03-05\ndescription: Newest post\n---\nnew\n")
+            .write("content/about.md", "---\ntitle: About\ndate: 2024-04-01\n---\nabout\n");
+        site.build("generate_rss: true\nposts_dir: posts\n").unwrap();
+
+        let xml = site.read(&format!("out/{}", RSS_FEED_PATH));
+        let document = roxmltree::Document::parse(&xml).unwrap_or_else(|e| panic!("{}\n{}", e, xml));
+        let child_text = |node: roxmltree::Node, name: &str| node.children()
+            .find(|child| child.has_tag_name(name))
+            .and_then(|child| child.text())
+            .unwrap_or_default()
+            .to_string();
+        let channel = document.descendants().find(|node| node.has_tag_name("channel")).unwrap();
+        assert_eq!(child_text(channel, "title"), "Test & Co");
+        assert_eq!(child_text(channel, "description"), "A test site");
+
+        let items = channel.children().filter(|node| node.has_tag_name("item")).collect::<Vec<_>>();
+        let fields = items.iter()
+            .map(|&item| (child_text(item, "title"), child_text(item, "link"), child_text(item, "description")))
+            .collect::<Vec<_>>();
+        assert_eq!(fields, [
+            ("New".to_string(), "https://www.example.com/posts/new.html".to_string(), "Newest post".to_string()),
+            ("Old".to_string(), "https://www.example.com/posts/old.html".to_string(), "The old post body.".to_string()),
+        ]);
+        let pub_date = chrono::DateTime::parse_from_rfc2822(&child_text(items[0], "pubDate")).unwrap();
+        assert_eq!(pub_date.date_naive(), chrono::NaiveDate::from_ymd_opt(2024, 3, 5).unwrap());
+    }
+
+    #[test]
+    fn heading_ids_are_stable_and_unique_and_listed_in_the_toc() {
+        let site = TestSite::new("heading-anchors");
+        site.write("templates/default.tpl.html", "<html><body>{{ toc }}\n{{ content }}</body></html>\n")
+            .write("content/page.md", "---\ntitle: Page\n---\n## Setup\n\n### Install `cargo`\n\n## Setup\n\n## Setup 1\n\n## Usage {#use}\n");
+        site.build("heading_anchors: true\n").unwrap();
+
+        let html = site.read("out/page.html");
+        for heading in ["<h2 id=\"setup\">Setup</h2>", "<h3 id=\"install-cargo\">Install <code>cargo</code></h3>", "<h2 id=\"setup-1\">Setup</h2>", "<h2 id=\"setup-1-1\">Setup 1</h2>", "<h2 id=\"use\">Usage</h2>"] {
+            assert!(html.contains(heading), "{}\n{}", heading, html);
+        }
+        assert!(html.contains(concat!(
+            "<nav class=\"toc\"><ul><li><a href=\"#setup\">Setup</a><ul><li><a href=\"#install-cargo\">Install cargo</a></li></ul></li>",
+            "<li><a href=\"#setup-1\">Setup</a></li><li><a href=\"#setup-1-1\">Setup 1</a></li><li><a href=\"#use\">Usage</a></li></ul></nav>",
+        )), "{}", html);
+
+        site.build("heading_anchors: true\n").unwrap();
+        assert_eq!(site.read("out/page.html"), html);
+    }
+
+    #[test]
+    fn excerpts_come_from_the_more_marker_or_the_first_words() {
+        let site = TestSite::new("excerpts");
+        site.write("templates/default.tpl.html", "<html><body><div class=\"excerpt\">{{ excerpt }}</div>{{ content }}</body></html>\n")
+            .write("content/marker.md", "---\ntitle: Marker\n---\nIntro **bold** text that is longer than three words.\n\nSecond intro paragraph.\n\n<!--more-->\n\nThe rest.\n")
+            .write("content/long.md", "---\ntitle: Long\n---\n## Heading\n\nOne two *three* four five.\n\nSecond paragraph.\n")
+            .write("content/short.md", "---\ntitle: Short\n---\nOne *two*.\n\nSecond paragraph.\n");
+        site.build("excerpt_words: 3\n").unwrap();
+
+        let excerpt = |path: &str| {
+            let html = site.read(path);
+            let start = html.find("<div class=\"excerpt\">").unwrap() + "<div class=\"excerpt\">".len();
+            html[start..start + html[start..].find("</div>").unwrap()].trim().to_string()
+        };
+        assert_eq!(excerpt("out/marker.html"), "<p>Intro <strong>bold</strong> text that is longer than three words.</p>\n<p>Second intro paragraph.</p>");
+        assert_eq!(excerpt("out/long.html"), "<p>One two three…</p>");
+        assert_eq!(excerpt("out/short.html"), "<p>One <em>two</em>.</p>");
+        assert!(site.read("out/marker.html").contains("<p>The rest.</p>"));
+    }
+
+    #[test]
+    fn sitemap_and_robots_default_to_the_output_root() {
+        let site = TestSite::new("sitemap-robots-paths");
+        site.write("content/page.md", "---\ntitle: Page\n---\npage\n");
+        site.build("generate_sitemap_xml: true\ngenerate_robots_txt: true\n").unwrap();
+
+        assert!(site.read("out/sitemap.xml").contains("<loc>https://www.example.com/page.html</loc>"));
+        assert!(site.read("out/robots.txt").contains("Sitemap: https://www.example.com/sitemap.xml"));
+        assert!(!site.root.join("out/assets/sitemap.xml").exists());
+        assert!(!site.root.join("out/assets/robots.txt").exists());
+
+        site.build("generate_sitemap_xml: true\ngenerate_robots_txt: true\nsitemap_path: /maps/site.xml\nrobots_path: seo/robots.txt\n").unwrap();
+        assert!(site.read("out/maps/site.xml").contains("<loc>https://www.example.com/page.html</loc>"));
+        let robots = site.read("out/seo/robots.txt");
+        assert!(robots.contains("Sitemap: https://www.example.com/maps/site.xml"), "{}", robots);
+    }
+
+    #[test]
+    fn unchanged_assets_are_not_copied_again() {
+        let site = TestSite::new("copy-unchanged");
+        let set_modified = |path: &str, secs: u64| {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            fs::File::options().write(true).open(site.root.join(path)).unwrap().set_modified(time).unwrap();
+        };
+        let modified = |path: &str| fs::metadata(site.root.join(path)).unwrap().modified().unwrap();
+        site.write("assets/same.txt", "same")
+            .write("assets/resized.txt", "new contents")
+            .write("assets/older.txt", "abcd")
+            .write("out/assets/same.txt", "same")
+            .write("out/assets/resized.txt", "old")
+            .write("out/assets/older.txt", "dcba");
+        for (path, secs) in [("assets/same.txt", 1_600_000_000), ("assets/resized.txt", 1_600_000_000), ("assets/older.txt", 1_700_000_000)] {
+            set_modified(path, secs);
+            set_modified(&format!("out/{}", path), 1_650_000_000);
+        }
+        let kept = modified("out/assets/same.txt");
+
+        let path = |path: &str| site.root.join(path).to_str().unwrap().to_string();
+        copy_assets(&path("assets"), &path("out/assets"), false, false).unwrap();
+
+        assert_eq!(modified("out/assets/same.txt"), kept);
+        assert_eq!(site.read("out/assets/resized.txt"), "new contents");
+        assert_eq!(site.read("out/assets/older.txt"), "abcd");
+    }
+
+    #[test]
+    fn page_styles_and_scripts_keep_commas_and_use_fingerprints() {
+        let site = TestSite::new("page-head");
+        site.write("templates/default.tpl.html", "<html><head>{{ page_head }}</head><body>{{ content }}{{ page_scripts }}</body></html>\n")
+            .write("assets/gallery.css", "body { color: red; }\n")
+            .write("content/page.md", concat!(
+                "---\ntitle: Page\n",
+                "styles: [\"https://fonts.googleapis.com/css2?family=Inter:wght@400,700&display=swap\", /assets/gallery.css]\n",
+                "scripts:\n  - /js/app.js\n  - //cdn.example.com/lib.js\n",
+                "---\npage\n",
+            ));
+        site.build("").unwrap();
+
+        let html = site.read("out/page.html");
+        assert!(html.contains(concat!(
+            "<link rel=\"stylesheet\" href=\"https://fonts.googleapis.com/css2?family=Inter:wght@400,700&amp;display=swap\">\n",
+            "<link rel=\"stylesheet\" href=\"https://www.example.com/assets/gallery.css\">",
+        )), "{}", html);
+        assert!(html.contains("<script src=\"https://www.example.com/js/app.js\"></script>\n<script src=\"//cdn.example.com/lib.js\"></script>"), "{}", html);
+
+        site.build("fingerprint_assets: true\n").unwrap();
+        let html = site.read("out/page.html");
+        let manifest = site.read("out/assets/asset-manifest.json");
+        let fingerprinted = manifest.split('"').find(|name| name.starts_with("gallery.") && name != &"gallery.css").unwrap();
+        assert!(html.contains(&format!("<link rel=\"stylesheet\" href=\"https://www.example.com/assets/{}\">", fingerprinted)), "{}", html);
+        assert_eq!(html.matches("<link ").count(), 2, "{}", html);
+    }
+
+    #[test]
+    fn editing_a_partial_changes_the_compiled_stylesheet_fingerprint() {
+        let site = TestSite::new("scss-fingerprint");
+        site.write("templates/default.tpl.html", "<html><head><link rel=\"stylesheet\" href=\"{{ asset \"main.css\" }}\"></head><body>{{ content }}</body></html>\n")
+            .write("assets/_vars.scss", "$accent: red;\n")
+            .write("assets/main.scss", "@use \"vars\";\nbody { color: vars.$accent; }\n")
+            .write("content/page.md", "---\ntitle: Page\n---\npage\n");
+        let settings = "compile_scss: true\nfingerprint_assets: true\nincremental: true\n";
+        let linked_stylesheet = || {
+            let html = site.read("out/page.html");
+            let start = html.find("/assets/").unwrap() + "/assets/".len();
+            html[start..start + html[start..].find('"').unwrap()].to_string()
+        };
+        site.build(settings).unwrap();
+        let red = linked_stylesheet();
+        assert!(red.starts_with("main.") && red != "main.css", "{}", red);
+        assert!(site.read(&format!("out/assets/{}", red)).contains("red"));
+
+        site.write("assets/_vars.scss", "$accent: blue;\n");
+        fs::File::options().write(true).open(site.root.join("assets/_vars.scss")).unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10)).unwrap();
+        site.build(settings).unwrap();
+        let blue = linked_stylesheet();
+        assert_ne!(blue, red);
+        assert!(site.read(&format!("out/assets/{}", blue)).contains("blue"));
+    }
+
+    #[test]
+    fn children_escape_page_titles() {
+        let site = TestSite::new("children-escape");
+        site.write("content/docs/index.md", "---\ntitle: Docs\n---\n{{ children }}\n")
+            .write("content/docs/fish.md", "---\ntitle: \"Fish & Chips <3\"\n---\nfish\n");
+        site.build("").unwrap();
+
+        let html = site.read("out/docs/index.html");
+        assert!(html.contains("<li><a href=\"https://www.example.com/docs/fish.html\">Fish &amp; Chips &lt;3</a></li>"), "{}", html);
+    }
+
+    #[test]
+    fn posts_by_year_escapes_post_titles() {
+        let site = TestSite::new("posts-by-year-escape");
+        site.write("content/archive.md", "---\ntitle: Archive\n---\n{{ posts_by_year }}\n")
+            .write("content/posts/fish.md", "---\ntitle: \"Fish & Chips <3\"\ndate: 2024-05-01\n---\nfish\n");
+        site.build("posts_dir: posts\n").unwrap();
+
+        let html = site.read("out/archive.html");
+        assert!(html.contains("<li><h3>2024</h3>\n<ul>\n<li><a href=\"https://www.example.com/posts/fish.html\">Fish &amp; Chips &lt;3</a></li>"), "{}", html);
+    }
+
+    #[test]
+    fn effective_config_json_is_valid_json() {
+        let site = TestSite::new("effective-config-json");
+        let config = site.config_with("default_priority: .nan\nsort_by: \"title \\\"quoted\\\"\"\n");
+        let json = effective_config(&config, "json").unwrap();
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap_or_else(|e| panic!("{}\n{}", e, json));
+
+        assert_eq!(value["config"]["default_priority"], serde_json::Value::Null);
+        assert_eq!(value["config"]["sort_by"], "title \"quoted\"");
+        assert_eq!(value["site"]["site.title"], "Test");
+        assert!(effective_config(&config, "toml").is_err());
+    }
+
+    #[test]
+    fn config_schemas_are_json_schemas_of_their_structs() {
+        let schema = serde_json::from_str::<serde_json::Value>(&json_schema_for::<Config>("meowdown-config.yaml").unwrap()).unwrap();
+        assert_eq!(schema["title"], "meowdown-config.yaml");
+        assert!(schema["properties"]["generate_rss"].is_object(), "{}", schema);
+        assert!(schema["properties"].get("changed_paths").is_none(), "{}", schema);
+
+        let schema = serde_json::from_str::<serde_json::Value>(&json_schema_for::<RobotsConfig>("data/robots_config.yaml").unwrap()).unwrap();
+        assert_eq!(schema["title"], "data/robots_config.yaml");
+        assert!(schema["properties"]["crawl_delay"].is_object(), "{}", schema);
+    }
+
+    #[test]
+    fn build_cache_is_json_read_back_by_the_next_build() {
+        let site = TestSite::new("build-cache-json");
+        site.write("content/page.md", "---\ntitle: Page\n---\npage\n");
+        site.build("incremental: true\n").unwrap();
+
+        let cache = serde_json::from_str::<serde_json::Value>(&site.read(&format!("out/{}", BUILD_CACHE_PATH))).unwrap();
+        let page = site.root.join("content/page.md").to_string_lossy().into_owned();
+        assert!(cache["pages"][&page]["outputs"]["page.html"].is_string(), "{}", cache);
+
+        site.write("out/page.html", "old");
+        site.build("incremental: true\n").unwrap();
+        assert_eq!(site.read("out/page.html"), "old");
+    }
+
+    #[test]
+    fn changes_json_lists_new_changed_and_removed_pages() {
+        let site = TestSite::new("changes-json");
+        site.write("content/kept.md", "---\ntitle: Kept\n---\nkept\n")
+            .write("content/edited.md", "---\ntitle: Edited\n---\nbefore\n")
+            .write("content/gone.md", "---\ntitle: Gone\n---\ngone\n");
+        let changes = || {
+            let json = serde_json::from_str::<serde_json::Value>(&site.read(&format!("out/{}", BUILD_CHANGES_PATH))).unwrap();
+            let paths = |key: &str| json[key].as_array().unwrap().iter()
+                .map(|entry| entry["path"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            (paths("new"), paths("changed"), paths("removed"))
+        };
+        site.build("changes_json: true\n").unwrap();
+        assert_eq!(changes().0, ["edited.html", "gone.html", "kept.html"]);
+        serde_json::from_str::<serde_json::Value>(&site.read(&format!("out/{}", BUILD_MANIFEST_PATH))).unwrap();
+
+        site.write("content/edited.md", "---\ntitle: Edited\n---\nafter\n")
+            .write("content/added.md", "---\ntitle: Added\n---\nadded\n");
+        fs::remove_file(site.root.join("content/gone.md")).unwrap();
+        site.build("changes_json: true\n").unwrap();
+        assert_eq!(changes(), (vec!["added.html".to_string()], vec!["edited.html".to_string()], vec!["gone.html".to_string()]));
+    }
+
+    #[test]
+    fn asset_manifest_maps_assets_to_their_fingerprinted_copies() {
+        let site = TestSite::new("asset-manifest");
+        site.write("assets/css/site.css", "body { margin: 0; }\n")
+            .write("assets/logo \"quoted\".svg", "<svg/>\n")
+            .write("content/page.md", "---\ntitle: Page\n---\npage\n");
+        site.build("fingerprint_assets: true\n").unwrap();
+
+        let manifest = serde_json::from_str::<BTreeMap<String, String>>(&site.read(&format!("out/assets/{}", ASSET_MANIFEST_PATH))).unwrap();
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["css/site.css", "logo \"quoted\".svg"]);
+        for (original, fingerprinted) in &manifest {
+            assert_ne!(original, fingerprinted);
+            assert_eq!(site.read(&format!("out/assets/{}", fingerprinted)), site.read(&format!("assets/{}", original)));
+        }
+    }
+
+    #[test]
+    fn breadcrumb_jsonld_is_json_with_one_item_per_level() {
+        let site = TestSite::new("breadcrumb-jsonld");
+        site.write("content/index.md", "---\ntitle: Home\n---\nhome\n")
+            .write("content/docs/index.md", "---\ntitle: \"Docs \\\"&\\\" </script>\"\n---\ndocs\n")
+            .write("content/docs/page.md", "---\ntitle: Page\n---\n{{ breadcrumb_jsonld }}\n");
+        site.build("").unwrap();
+
+        let html = site.read("out/docs/page.html");
+        let start = html.find("<script type=\"application/ld+json\">\n").unwrap() + "<script type=\"application/ld+json\">\n".len();
+        let script = &html[start..start + html[start..].find("\n</script>").unwrap()];
+        assert!(!script.contains("</"), "{}", script);
+        let json = serde_json::from_str::<serde_json::Value>(script).unwrap_or_else(|e| panic!("{}\n{}", e, script));
+        assert_eq!(json["@type"], "BreadcrumbList");
+        let items = json["itemListElement"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1]["name"], "Docs \"&\" </script>");
+        assert_eq!(items[1]["position"], 2);
+        assert_eq!(items[2]["item"], "https://www.example.com/docs/page.html");
+    }
+}